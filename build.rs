@@ -0,0 +1,7 @@
+fn main() {
+    // Плагины регистрируют дескрипторы для хранилища systemd через dlsym, поэтому
+    // функция регистрации экспортируется из исполняемого файла
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("linux") {
+        println!("cargo:rustc-link-arg-bins=-Wl,--export-dynamic-symbol=anisystemd_fdstore_register");
+    }
+}
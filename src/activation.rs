@@ -0,0 +1,102 @@
+use anyhow::Result;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use tracing::{info, warn};
+
+/// Переменная окружения, через которую сокеты передаются боту и плагинам в этом процессе
+///
+/// Формат: `имя=fd` через запятую, например `http=3,metrics=4`.
+pub const SOCKETS_ENV: &str = "ANISYSTEMD_SOCKETS";
+
+/// Именованные файловые дескрипторы, полученные от systemd через `LISTEN_FDS`
+#[derive(Debug, Default)]
+pub struct ActivatedSockets {
+    fds: Vec<(String, OwnedFd)>,
+}
+
+impl ActivatedSockets {
+    /// Получение дескрипторов от systemd (только на Linux)
+    ///
+    /// `LISTEN_*` удаляются из окружения, чтобы их не унаследовали дочерние процессы.
+    /// Принимаются только сокеты, остальные дескрипторы закрываются с предупреждением
+    /// (например, лишние записи в хранилище дескрипторов systemd не должны мешать запуску).
+    /// На всех принятых дескрипторах выставляется `FD_CLOEXEC`.
+    #[cfg(target_os = "linux")]
+    pub fn receive() -> Result<Self> {
+        use libsystemd::activation::IsType;
+        use std::os::fd::{FromRawFd, IntoRawFd};
+
+        let received = libsystemd::activation::receive_descriptors_with_names(true)
+            .map_err(|e| anyhow::anyhow!("Failed to receive LISTEN_FDS: {}", e))?;
+
+        let mut fds = Vec::with_capacity(received.len());
+        let mut seen = Vec::new();
+        for (descriptor, name) in received {
+            let is_socket = descriptor.is_inet() || descriptor.is_unix();
+            // SAFETY: systemd передал дескриптор процессу, и он больше нигде не используется
+            let fd = unsafe { OwnedFd::from_raw_fd(descriptor.into_raw_fd()) };
+
+            if !is_socket {
+                warn!("Closing descriptor {} ({:?}) passed by systemd: not a socket", fd.as_raw_fd(), name);
+                continue;
+            }
+            // Сокет из .socket-юнита приходит еще раз из хранилища дескрипторов, куда его
+            // сохранил предыдущий запуск: копия того же сокета не нужна
+            let id = crate::fdstore::socket_id(fd.as_raw_fd());
+            if id.is_some() && seen.contains(&id) {
+                info!("Closing duplicate of socket {:?} from systemd fd store (fd {})", name, fd.as_raw_fd());
+                continue;
+            }
+            seen.push(id);
+            set_cloexec(&fd)?;
+
+            info!("Received socket {:?} from systemd (fd {})", name, fd.as_raw_fd());
+            fds.push((name, fd));
+        }
+
+        Ok(Self { fds })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn receive() -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    /// Сделать сокеты доступными боту и плагинам через [`SOCKETS_ENV`]
+    ///
+    /// `Bot` не принимает параметров, поэтому сокеты публикуются в окружении: вызывать
+    /// до запуска runtime, пока окружение не читают другие потоки.
+    /// Дескрипторы остаются открытыми, пока жив этот объект.
+    pub fn export(&self) {
+        if self.fds.is_empty() {
+            return;
+        }
+
+        let value = self
+            .fds
+            .iter()
+            .map(|(name, fd)| format!("{}={}", name, fd.as_raw_fd()))
+            .collect::<Vec<_>>()
+            .join(",");
+        std::env::set_var(SOCKETS_ENV, &value);
+        info!("Exported {} activated socket(s) via {}: {}", self.fds.len(), SOCKETS_ENV, value);
+    }
+
+    /// Зарегистрировать сокеты в хранилище дескрипторов, чтобы они пережили перезапуск
+    pub fn preserve(&self) {
+        for (name, fd) in &self.fds {
+            if let Err(e) = crate::fdstore::register(name, fd.as_fd()) {
+                warn!("Failed to register activated socket {:?} for systemd fd store: {}", name, e);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_cloexec(fd: &OwnedFd) -> Result<()> {
+    // SAFETY: fcntl работает с открытым дескриптором, которым мы владеем
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    if flags < 0 || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
@@ -0,0 +1,382 @@
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+use crate::crash_loop::CrashLoopPolicy;
+use crate::library::LibraryNaming;
+use crate::signature::TrustPolicy;
+use crate::store::PluginCommand;
+use crate::validate::PluginRequirements;
+use crate::watcher::{WatchBackend, WatchSettings};
+
+/// Файл конфигурации, который читается, если он есть в рабочей директории
+const DEFAULT_CONFIG_FILE: &str = "anisystemd.toml";
+
+/// Директория плагинов по умолчанию
+const DEFAULT_PLUGIN_DIR: &str = "./plugins";
+
+/// Тихий период мониторинга плагинов по умолчанию
+const DEFAULT_WATCH_DEBOUNCE: Duration = Duration::from_millis(2000);
+
+/// Интервал опроса директорий плагинов по умолчанию
+const DEFAULT_WATCH_POLL_INTERVAL: Duration = Duration::from_millis(2000);
+
+/// Игнорируемые мониторингом файлы по умолчанию: скрытые, временные и недокачанные
+const DEFAULT_WATCH_IGNORE: &[&str] = &[".*", "*.tmp", "*.part", "*.partial", "*.swp", "*~"];
+
+/// Директория состояния, если ее не задали ни явно, ни через StateDirectory=
+const DEFAULT_STATE_DIR: &str = "state";
+
+/// Сколько версий каждого плагина хранить по умолчанию
+const DEFAULT_STORE_KEEP_VERSIONS: usize = 3;
+
+/// Таймаут завершения по умолчанию, заметно меньше TimeoutStopSec= по умолчанию (90s)
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Параметры командной строки (каждый можно задать и переменной окружения)
+#[derive(Debug, Parser)]
+#[command(name = "anisystemd", version, about = "systemd supervisor for anicore bot")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Путь к файлу конфигурации
+    #[arg(long, env = "ANISYSTEMD_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Директории плагинов (можно повторять или перечислить через ':')
+    #[arg(long = "plugins-dir", env = "ANISYSTEMD_PLUGINS_DIR", value_delimiter = ':')]
+    plugin_dirs: Vec<PathBuf>,
+
+    /// Директория для журнала запусков и карантина плагинов
+    #[arg(long, env = "ANISYSTEMD_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Тихий период, после которого накопленные изменения плагинов обрабатываются (в миллисекундах)
+    #[arg(long, env = "ANISYSTEMD_WATCH_DEBOUNCE_MS")]
+    watch_debounce_ms: Option<u64>,
+
+    /// Способ мониторинга директорий плагинов
+    #[arg(long, env = "ANISYSTEMD_WATCH_BACKEND", value_enum)]
+    watch_backend: Option<WatchBackend>,
+
+    /// Интервал опроса директорий плагинов (в миллисекундах)
+    #[arg(long, env = "ANISYSTEMD_WATCH_POLL_INTERVAL_MS")]
+    watch_poll_interval_ms: Option<u64>,
+
+    /// Шаблоны имен, изменения которых мониторинг игнорирует (можно повторять или перечислить через ',')
+    #[arg(long = "watch-ignore", env = "ANISYSTEMD_WATCH_IGNORE", value_delimiter = ',')]
+    watch_ignore: Vec<String>,
+
+    /// Перезапускать бота и тогда, когда у плагина изменились только метаданные (touch, chmod)
+    #[arg(long, env = "ANISYSTEMD_WATCH_RESTART_ON_TOUCH", action = clap::ArgAction::Set)]
+    watch_restart_on_touch: Option<bool>,
+
+    /// Принимать только эти плагины, по имени файла без префикса и расширения (можно перечислить через ',')
+    #[arg(long = "plugin-allow", env = "ANISYSTEMD_PLUGIN_ALLOW", value_delimiter = ',')]
+    plugin_allow: Vec<String>,
+
+    /// Никогда не принимать эти плагины (можно перечислить через ',')
+    #[arg(long = "plugin-deny", env = "ANISYSTEMD_PLUGIN_DENY", value_delimiter = ',')]
+    plugin_deny: Vec<String>,
+
+    /// Проверять измененные плагины в отдельном процессе перед перезапуском
+    #[arg(long, env = "ANISYSTEMD_VALIDATE_PLUGINS", action = clap::ArgAction::Set)]
+    validate_plugins: Option<bool>,
+
+    /// Публичные ключи ed25519 (hex), которыми должны быть подписаны плагины (можно перечислить через ',')
+    #[arg(long = "trusted-key", env = "ANISYSTEMD_TRUSTED_KEYS", value_delimiter = ',')]
+    trusted_keys: Vec<String>,
+
+    /// Порог зависания бота для watchdog (в секундах), меньше таймаута watchdog systemd
+    #[arg(long, env = "ANISYSTEMD_WATCHDOG_STALL_SECS")]
+    watchdog_stall_secs: Option<u64>,
+
+    /// Время на завершение текущей работы бота при остановке (в секундах)
+    #[arg(long, env = "ANISYSTEMD_DRAIN_TIMEOUT_SECS")]
+    drain_timeout_secs: Option<u64>,
+}
+
+/// Команды, которые выполняются вместо запуска демона
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Управление хранилищем версий плагинов
+    #[command(subcommand)]
+    Plugin(PluginCommand),
+}
+
+/// Содержимое файла конфигурации
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    plugin_dirs: Vec<PathBuf>,
+    state_dir: Option<PathBuf>,
+    watch_debounce_ms: Option<u64>,
+    watch_backend: Option<WatchBackend>,
+    watch_poll_interval_ms: Option<u64>,
+    watch_ignore: Option<Vec<String>>,
+    watch_restart_on_touch: Option<bool>,
+    library_extensions: Option<Vec<String>>,
+    library_prefixes: Option<Vec<String>>,
+    library_suffixes: Option<Vec<String>>,
+    plugin_allow: Vec<String>,
+    plugin_deny: Vec<String>,
+    validate_plugins: Option<bool>,
+    plugin_required_symbols: Vec<String>,
+    plugin_abi_symbol: Option<String>,
+    plugin_abi_version: Option<u32>,
+    plugin_trusted_keys: Vec<String>,
+    store_keep_versions: Option<usize>,
+    crash_loop_max_failures: Option<usize>,
+    crash_loop_window_secs: Option<u64>,
+    crash_loop_stable_secs: Option<u64>,
+    watchdog_stall_secs: Option<u64>,
+    drain_timeout_secs: Option<u64>,
+}
+
+/// Итоговая конфигурация демона
+///
+/// Приоритет источников: командная строка, переменные окружения, файл конфигурации.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Команда вместо запуска демона
+    pub command: Option<Command>,
+    /// Абсолютные пути директорий плагинов
+    pub plugin_dirs: Vec<PathBuf>,
+    /// Абсолютный путь директории состояния
+    pub state_dir: PathBuf,
+    /// Настройки мониторинга директорий плагинов
+    pub watch: WatchSettings,
+    /// Какие файлы считаются библиотеками плагинов и сервисов
+    pub library_naming: LibraryNaming,
+    /// Требования к измененным плагинам, `None` — проверка отключена
+    pub plugin_validation: Option<PluginRequirements>,
+    /// Политика доверия к подписям плагинов, `None` — подписи не проверяются
+    pub trust_policy: Option<TrustPolicy>,
+    /// Сколько версий каждого плагина хранить в хранилище
+    pub store_keep_versions: usize,
+    /// Обнаружение циклических падений, `None` — отключено
+    pub crash_loop: Option<CrashLoopPolicy>,
+    /// Порог зависания бота, `None` — половина таймаута watchdog systemd
+    pub watchdog_stall_threshold: Option<Duration>,
+    /// Время на завершение текущей работы бота
+    pub drain_timeout: Duration,
+}
+
+impl Config {
+    /// Загрузка конфигурации из командной строки, окружения и файла
+    pub fn load() -> Result<Self> {
+        Self::load_with(&BTreeMap::new())
+    }
+
+    /// Загрузка конфигурации, в которой переменные `env` заменяют одноименные переменные окружения
+    ///
+    /// `None` — переменная считается незаданной. Окружение процесса при этом не меняется:
+    /// его читают и другие потоки (бот, мониторинг).
+    pub fn load_with(env: &BTreeMap<String, Option<String>>) -> Result<Self> {
+        let command = Cli::command().mut_args(|arg| {
+            let value = arg.get_env().and_then(|name| env.get(name.to_str()?)).cloned();
+            match value {
+                // Как и переменная окружения, значение уступает командной строке
+                Some(Some(value)) => arg.env(None::<&str>).default_value(value),
+                Some(None) => arg.env(None::<&str>),
+                None => arg,
+            }
+        });
+        let cli = match command.try_get_matches().and_then(|matches| Cli::from_arg_matches(&matches)) {
+            Ok(cli) => cli,
+            // --help и --version печатаются и завершают процесс как обычно
+            Err(e) if !e.use_stderr() => e.exit(),
+            Err(e) => bail!("{}", e),
+        };
+
+        let (file, file_dir) = match &cli.config {
+            Some(path) => (read_file(path)?, parent_dir(path)?),
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                (read_file(Path::new(DEFAULT_CONFIG_FILE))?, std::env::current_dir()?)
+            }
+            None => (FileConfig::default(), std::env::current_dir()?),
+        };
+
+        // Относительные пути из файла считаются от директории файла, остальные — от рабочей директории
+        let plugin_dirs = if !cli.plugin_dirs.is_empty() {
+            resolve_dirs(&cli.plugin_dirs, &std::env::current_dir()?, false)?
+        } else if !file.plugin_dirs.is_empty() {
+            resolve_dirs(&file.plugin_dirs, &file_dir, false)?
+        } else {
+            resolve_dirs(&[PathBuf::from(DEFAULT_PLUGIN_DIR)], &std::env::current_dir()?, true)?
+        };
+
+        let quiet_period = cli
+            .watch_debounce_ms
+            .or(file.watch_debounce_ms)
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_WATCH_DEBOUNCE);
+
+        let backend = cli.watch_backend.or(file.watch_backend).unwrap_or_default();
+        let poll_interval = match cli.watch_poll_interval_ms.or(file.watch_poll_interval_ms) {
+            Some(0) => bail!("watch_poll_interval_ms must be greater than zero"),
+            ms => ms.map(Duration::from_millis).unwrap_or(DEFAULT_WATCH_POLL_INTERVAL),
+        };
+
+        // Заданный список шаблонов заменяет список по умолчанию
+        let ignore = if !cli.watch_ignore.is_empty() {
+            cli.watch_ignore
+        } else {
+            let defaults = || DEFAULT_WATCH_IGNORE.iter().map(|pattern| pattern.to_string()).collect();
+            file.watch_ignore.unwrap_or_else(defaults)
+        };
+        let watch = WatchSettings {
+            quiet_period,
+            backend,
+            poll_interval,
+            ignore: glob_set(&ignore)?,
+            restart_on_touch: cli.watch_restart_on_touch.or(file.watch_restart_on_touch).unwrap_or(false),
+        };
+
+        // По умолчанию — именование динамических библиотек текущей платформы
+        let defaults = LibraryNaming::default();
+        let library_naming = LibraryNaming {
+            extensions: file
+                .library_extensions
+                .map(|extensions| extensions.iter().map(|ext| ext.trim_start_matches('.').to_string()).collect())
+                .unwrap_or(defaults.extensions),
+            prefixes: file.library_prefixes.unwrap_or(defaults.prefixes),
+            suffixes: file.library_suffixes.unwrap_or(defaults.suffixes),
+            allow: if cli.plugin_allow.is_empty() { file.plugin_allow } else { cli.plugin_allow },
+            deny: if cli.plugin_deny.is_empty() { file.plugin_deny } else { cli.plugin_deny },
+        };
+        if library_naming.extensions.is_empty() || library_naming.suffixes.is_empty() {
+            bail!("library_extensions and library_suffixes must not be empty");
+        }
+
+        let abi = match (file.plugin_abi_symbol, file.plugin_abi_version) {
+            (Some(symbol), Some(version)) => Some((symbol, version)),
+            (None, None) => None,
+            _ => bail!("plugin_abi_symbol and plugin_abi_version must be set together"),
+        };
+        let plugin_validation = cli
+            .validate_plugins
+            .or(file.validate_plugins)
+            .unwrap_or(true)
+            .then_some(PluginRequirements {
+                required_symbols: file.plugin_required_symbols,
+                abi,
+            });
+
+        let trusted_keys = if cli.trusted_keys.is_empty() { file.plugin_trusted_keys } else { cli.trusted_keys };
+        let trust_policy = if trusted_keys.is_empty() {
+            None
+        } else {
+            Some(TrustPolicy::from_hex_keys(&trusted_keys)?)
+        };
+
+        // StateDirectory= может содержать несколько путей через ':', берем первый
+        let state_dir = match cli.state_dir {
+            Some(dir) => std::env::current_dir()?.join(dir),
+            None => match (file.state_dir, std::env::var_os("STATE_DIRECTORY")) {
+                (Some(dir), _) => file_dir.join(dir),
+                (None, Some(dirs)) => std::env::split_paths(&dirs).next().unwrap_or_default(),
+                (None, None) => std::env::current_dir()?.join(DEFAULT_STATE_DIR),
+            },
+        };
+
+        let store_keep_versions = file.store_keep_versions.unwrap_or(DEFAULT_STORE_KEEP_VERSIONS);
+
+        let crash_loop = match file.crash_loop_max_failures.unwrap_or(3) {
+            0 => None,
+            max_failures => Some(CrashLoopPolicy {
+                max_failures,
+                window: Duration::from_secs(file.crash_loop_window_secs.unwrap_or(600)),
+                stable_after: Duration::from_secs(file.crash_loop_stable_secs.unwrap_or(60)),
+            }),
+        };
+
+        let watchdog_stall_threshold = match cli.watchdog_stall_secs.or(file.watchdog_stall_secs) {
+            Some(0) => bail!("watchdog_stall_secs must be greater than zero"),
+            secs => secs.map(Duration::from_secs),
+        };
+
+        let drain_timeout = cli
+            .drain_timeout_secs
+            .or(file.drain_timeout_secs)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+
+        Ok(Self {
+            command: cli.command,
+            plugin_dirs,
+            state_dir,
+            watch,
+            library_naming,
+            plugin_validation,
+            trust_policy,
+            store_keep_versions,
+            crash_loop,
+            watchdog_stall_threshold,
+            drain_timeout,
+        })
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {:?}", path))?;
+    let config = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse config file {:?}", path))?;
+    info!("Loaded config file {:?}", path);
+    Ok(config)
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("Invalid watch_ignore pattern {:?}", pattern))?);
+    }
+    Ok(builder.build()?)
+}
+
+fn parent_dir(path: &Path) -> Result<PathBuf> {
+    let absolute = std::env::current_dir()?.join(path);
+    Ok(absolute.parent().map(Path::to_path_buf).unwrap_or(absolute))
+}
+
+/// Приведение директорий плагинов к абсолютным путям с проверкой
+///
+/// Дубликаты отбрасываются. Отсутствующие директории создаются только с `create_missing`
+/// (директория по умолчанию): опечатка в явно заданном пути — ошибка конфигурации,
+/// а не новая пустая директория, в которой бот молча запустится без плагинов.
+fn resolve_dirs(dirs: &[PathBuf], base: &Path, create_missing: bool) -> Result<Vec<PathBuf>> {
+    let mut resolved: Vec<PathBuf> = Vec::with_capacity(dirs.len());
+
+    for dir in dirs {
+        let path = base.join(dir);
+
+        if !path.exists() {
+            if !create_missing {
+                bail!("Plugins directory {:?} does not exist", path);
+            }
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create plugins directory {:?}", path))?;
+            info!("Created plugins directory: {:?}", path);
+        }
+
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve plugins directory {:?}", path))?;
+        if !path.is_dir() {
+            bail!("Plugins path {:?} is not a directory", path);
+        }
+
+        if !resolved.contains(&path) {
+            resolved.push(path);
+        }
+    }
+
+    Ok(resolved)
+}
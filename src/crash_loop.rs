@@ -0,0 +1,465 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+use crate::exit_code::ExitReason;
+use crate::library::LibraryNaming;
+use crate::quarantine;
+use crate::store::{self, PluginStore};
+
+/// Сколько последних запусков хранится в истории
+const HISTORY_LEN: usize = 16;
+
+/// Поддиректория директории состояния с копиями последнего рабочего набора плагинов
+const LAST_GOOD_DIR: &str = "last-good";
+
+/// Отпечаток библиотеки плагина: размер и время изменения
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub len: u64,
+    pub mtime: u64,
+}
+
+/// Набор библиотек плагинов: путь → отпечаток
+pub type PluginSet = BTreeMap<String, Fingerprint>;
+
+/// Настройки обнаружения циклических падений
+#[derive(Debug, Clone)]
+pub struct CrashLoopPolicy {
+    /// Сколько неудачных запусков подряд считается циклом падений
+    pub max_failures: usize,
+    /// Окно, в которое должны уложиться неудачные запуски
+    pub window: Duration,
+    /// Через сколько времени работы запуск считается успешным
+    pub stable_after: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StartRecord {
+    started_at: u64,
+    succeeded: bool,
+    /// Процесс завершился штатно (остановка или запрошенный перезапуск) до `stable_after`
+    #[serde(default)]
+    clean_exit: bool,
+    plugins: PluginSet,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct History {
+    #[serde(default)]
+    starts: Vec<StartRecord>,
+    last_good: Option<PluginSet>,
+}
+
+/// Журнал запусков в директории состояния
+///
+/// ```text
+/// <state_dir>/starts.toml    история запусков и последний рабочий набор плагинов
+/// <state_dir>/last-good/     копии плагинов последнего рабочего набора
+/// <state_dir>/quarantine/    плагины, откатанные после цикла падений
+/// ```
+///
+/// Копии и карантин повторяют расположение плагинов: `<номер директории плагинов>/<путь>`,
+/// см. [`quarantine::backup_key`].
+pub struct StartJournal {
+    state_dir: PathBuf,
+    plugin_dirs: Vec<PathBuf>,
+    naming: LibraryNaming,
+    /// Хранилище версий: его плагины откатываются переключением активной версии
+    store: Option<PluginStore>,
+    policy: CrashLoopPolicy,
+}
+
+impl StartJournal {
+    pub fn new(
+        state_dir: &Path,
+        plugin_dirs: &[PathBuf],
+        naming: &LibraryNaming,
+        store: Option<PluginStore>,
+        policy: CrashLoopPolicy,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(state_dir)
+            .with_context(|| format!("Failed to create state directory {:?}", state_dir))?;
+        Ok(Self {
+            state_dir: state_dir.to_path_buf(),
+            plugin_dirs: plugin_dirs.to_vec(),
+            naming: naming.clone(),
+            store,
+            policy,
+        })
+    }
+
+    /// Проверить историю, при цикле падений откатить плагины и записать новый запуск
+    ///
+    /// Возвращает `true`, если плагины были откачены.
+    pub fn begin(&self) -> Result<bool> {
+        let mut history = self.load();
+        let mut plugins = self.current_plugins();
+        let mut rolled_back = false;
+
+        if let Some(last_good) = history.last_good.clone() {
+            if plugins != last_good && self.in_crash_loop(&history, &plugins) {
+                error!(
+                    "Detected {} failed starts within {:?} after a plugin change, rolling back plugins",
+                    self.policy.max_failures, self.policy.window
+                );
+                self.roll_back(&plugins, &last_good)?;
+                history.starts.clear();
+                plugins = self.current_plugins();
+                rolled_back = true;
+            }
+        }
+
+        history.starts.push(StartRecord {
+            started_at: unix_now(),
+            succeeded: false,
+            clean_exit: false,
+            plugins,
+        });
+        let excess = history.starts.len().saturating_sub(HISTORY_LEN);
+        history.starts.drain(..excess);
+        self.save(&history)?;
+
+        Ok(rolled_back)
+    }
+
+    /// Отметить текущий запуск успешным и сохранить копии его плагинов
+    pub fn mark_good(&self) -> Result<()> {
+        let mut history = self.load();
+        let Some(current) = history.starts.last_mut() else {
+            return Ok(());
+        };
+        if current.succeeded {
+            return Ok(());
+        }
+        current.succeeded = true;
+        let plugins = current.plugins.clone();
+
+        self.snapshot_last_good(&plugins)?;
+        history.last_good = Some(plugins);
+        self.save(&history)?;
+        info!("Current plugin set recorded as last known good");
+        Ok(())
+    }
+
+    /// Отметить штатное завершение процесса, чтобы короткий запуск не считался падением
+    ///
+    /// Ошибки бота и загрузки плагинов остаются неудачными запусками.
+    pub fn mark_exit(&self, reason: ExitReason) -> Result<()> {
+        if !matches!(reason, ExitReason::Clean | ExitReason::RestartRequested) {
+            return Ok(());
+        }
+
+        let mut history = self.load();
+        let Some(current) = history.starts.last_mut() else {
+            return Ok(());
+        };
+        current.clean_exit = true;
+        self.save(&history)
+    }
+
+    /// Отметить запуск успешным, когда бот проработает `stable_after`
+    pub fn spawn_mark_good(self: std::sync::Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            tokio::time::sleep(self.policy.stable_after).await;
+            if let Err(e) = self.mark_good() {
+                warn!("Failed to record successful start: {:?}", e);
+            }
+        })
+    }
+
+    fn in_crash_loop(&self, history: &History, plugins: &PluginSet) -> bool {
+        let since = unix_now().saturating_sub(self.policy.window.as_secs());
+        let failures = history
+            .starts
+            .iter()
+            .rev()
+            .take_while(|start| {
+                !start.succeeded && !start.clean_exit && start.started_at >= since && &start.plugins == plugins
+            })
+            .count();
+        failures >= self.policy.max_failures
+    }
+
+    /// Вернуть плагины к последнему рабочему набору
+    ///
+    /// Новые и измененные плагины переносятся в карантин, измененные и удаленные
+    /// восстанавливаются из копий последнего рабочего набора. Плагин в поддиректории
+    /// переносится и восстанавливается вместе со всей поддиректорией, библиотека в директории
+    /// плагинов — вместе с подписью и манифестом. Для плагинов из хранилища вместо этого
+    /// активируется версия последнего рабочего набора.
+    fn roll_back(&self, plugins: &PluginSet, last_good: &PluginSet) -> Result<()> {
+        let quarantine = quarantine::batch_dir(&self.state_dir);
+        let last_good_dir = last_good_dir(&self.state_dir);
+
+        let changed = plugins.iter().filter(|(path, fingerprint)| last_good.get(*path) != Some(fingerprint));
+        for file in self.plugin_files(changed.map(|(path, _)| path)) {
+            if self.last_good_release(&file).is_some() {
+                continue;
+            }
+            let Some(key) = quarantine::backup_key(&file, &self.plugin_dirs) else {
+                continue;
+            };
+            let target = quarantine.join(key);
+            quarantine::move_path(&file, &target)
+                .with_context(|| format!("Failed to quarantine plugin {:?}", file))?;
+            warn!("Quarantined plugin {:?} to {:?}", file, target);
+        }
+
+        let lost = last_good.iter().filter(|(path, fingerprint)| plugins.get(*path) != Some(fingerprint));
+        let mut restored = BTreeSet::new();
+        for library in lost.map(|(path, _)| Path::new(path)) {
+            let Some(backup) = quarantine::backup_of(library, &self.plugin_dirs, &last_good_dir) else {
+                error!("No last known good copy of plugin {:?}", library);
+                continue;
+            };
+            if !restored.insert(backup.clone()) {
+                continue;
+            }
+            if let Some((name, version)) = self.last_good_release(library) {
+                let activated = match &self.store {
+                    Some(store) => store.activate(&name, &version),
+                    None => Err(anyhow::anyhow!("plugin store is not available")),
+                };
+                match activated {
+                    Ok(()) => warn!("Activated last known good {} version {}", name, version),
+                    Err(e) => error!("Failed to activate {} version {}: {:?}", name, version, e),
+                }
+                continue;
+            }
+            match quarantine::restore(library, &self.plugin_dirs, &last_good_dir) {
+                Ok(()) => warn!("Restored last known good plugin {:?}", library),
+                Err(e) => error!("Failed to restore plugin {:?} from {:?}: {}", library, backup, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Сохранить копии плагинов рабочего набора в `last-good/`
+    fn snapshot_last_good(&self, plugins: &PluginSet) -> Result<()> {
+        let last_good_dir = last_good_dir(&self.state_dir);
+        let staging = last_good_dir.with_extension("tmp");
+
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        std::fs::create_dir_all(&staging)?;
+        for file in self.plugin_files(plugins.keys()) {
+            let Some(key) = quarantine::backup_key(&file, &self.plugin_dirs) else {
+                continue;
+            };
+            let backup = staging.join(key);
+            // Версии остаются в хранилище, для его плагинов достаточно запомнить активную
+            let copied = match self.store.as_ref().and_then(|store| store.active_release(&file)) {
+                Some(_) => copy_link(&file, &backup),
+                None => quarantine::copy_path(&file, &backup),
+            };
+            copied.with_context(|| format!("Failed to back up plugin {:?}", file))?;
+        }
+
+        if last_good_dir.exists() {
+            std::fs::remove_dir_all(&last_good_dir)?;
+        }
+        std::fs::rename(&staging, &last_good_dir)?;
+        Ok(())
+    }
+
+    /// Версия из хранилища, активная для библиотеки в последнем рабочем наборе
+    fn last_good_release(&self, library: &Path) -> Option<(String, String)> {
+        let backup = quarantine::backup_of(library, &self.plugin_dirs, &last_good_dir(&self.state_dir))?;
+        store::release_of(&std::fs::read_link(backup).ok()?)
+    }
+
+    /// Что копируется и переносится для библиотек, см. [`quarantine::plugin_files`]
+    fn plugin_files<'a>(&self, libraries: impl Iterator<Item = &'a String>) -> BTreeSet<PathBuf> {
+        libraries
+            .flat_map(|library| quarantine::plugin_files(Path::new(library), &self.plugin_dirs))
+            .collect()
+    }
+
+    fn current_plugins(&self) -> PluginSet {
+        crate::watcher::scan(&self.plugin_dirs, &self.naming)
+            .into_iter()
+            .filter_map(|(path, state)| {
+                let (len, mtime, _) = state?;
+                let mtime = mtime.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                Some((path.to_string_lossy().into_owned(), Fingerprint { len, mtime }))
+            })
+            .collect()
+    }
+
+    fn history_path(&self) -> PathBuf {
+        self.state_dir.join("starts.toml")
+    }
+
+    fn load(&self) -> History {
+        let path = self.history_path();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring corrupted start history {:?}: {}", path, e);
+                History::default()
+            }),
+            Err(_) => History::default(),
+        }
+    }
+
+    fn save(&self, history: &History) -> Result<()> {
+        let path = self.history_path();
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, toml::to_string(history)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+/// Копии последнего рабочего набора плагинов: `<state_dir>/last-good/`, разложенные
+/// по [`quarantine::backup_key`]
+pub fn last_good_dir(state_dir: &Path) -> PathBuf {
+    state_dir.join(LAST_GOOD_DIR)
+}
+
+/// Скопировать саму символическую ссылку, а не файл, на который она указывает
+fn copy_link(from: &Path, to: &Path) -> Result<()> {
+    let target = std::fs::read_link(from)?;
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    store::symlink(&target, to)?;
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{library, TempDir};
+
+    fn policy() -> CrashLoopPolicy {
+        CrashLoopPolicy {
+            max_failures: 3,
+            window: Duration::from_secs(600),
+            stable_after: Duration::from_secs(60),
+        }
+    }
+
+    fn journal(dir: &TempDir) -> StartJournal {
+        let plugin_dirs = [dir.path().join("plugins"), dir.path().join("local")];
+        StartJournal::new(&dir.path().join("state"), &plugin_dirs, &LibraryNaming::default(), None, policy()).unwrap()
+    }
+
+    fn plugins(mtime: u64) -> PluginSet {
+        PluginSet::from([(library("/plugins/foo_plugin"), Fingerprint { len: 1, mtime })])
+    }
+
+    /// Запуск `age` секунд назад
+    fn start(plugins: &PluginSet, age: u64, succeeded: bool, clean_exit: bool) -> StartRecord {
+        StartRecord {
+            started_at: unix_now() - age,
+            succeeded,
+            clean_exit,
+            plugins: plugins.clone(),
+        }
+    }
+
+    fn history(starts: Vec<StartRecord>) -> History {
+        History { starts, last_good: None }
+    }
+
+    #[test]
+    fn crash_loop_counts_consecutive_failures_with_same_plugins() {
+        let dir = TempDir::new("crash-loop");
+        let journal = journal(&dir);
+        let (current, previous) = (plugins(2), plugins(1));
+        let failure = |age| start(&current, age, false, false);
+
+        assert!(journal.in_crash_loop(&history(vec![failure(30), failure(20), failure(10)]), &current));
+        assert!(!journal.in_crash_loop(&history(vec![failure(20), failure(10)]), &current));
+        // Неудачи с другим набором плагинов, слишком старые и до успешного запуска не считаются
+        let other_plugins = start(&previous, 30, false, false);
+        assert!(!journal.in_crash_loop(&history(vec![other_plugins, failure(20), failure(10)]), &current));
+        assert!(!journal.in_crash_loop(&history(vec![failure(700), failure(20), failure(10)]), &current));
+        let succeeded = start(&current, 20, true, false);
+        assert!(!journal.in_crash_loop(&history(vec![failure(30), succeeded, failure(10)]), &current));
+    }
+
+    #[test]
+    fn clean_exits_are_not_failures() {
+        let dir = TempDir::new("crash-loop");
+        let journal = journal(&dir);
+        let current = plugins(1);
+        let failure = |age| start(&current, age, false, false);
+        let clean_exit = start(&current, 20, false, true);
+
+        assert!(!journal.in_crash_loop(&history(vec![failure(40), failure(30), clean_exit, failure(10)]), &current));
+    }
+
+    #[test]
+    fn roll_back_restores_last_good_plugins() {
+        let dir = TempDir::new("roll-back");
+        let journal = journal(&dir);
+        let shipped = dir.write(&format!("plugins/{}", library("x_plugin")), "shipped");
+        let local = dir.write(&format!("local/{}", library("x_plugin")), "local");
+        dir.write(&format!("plugins/foo/{}", library("foo_plugin")), "foo");
+        let data = dir.write("plugins/foo/data/config.json", "{}");
+
+        let last_good = journal.current_plugins();
+        journal.snapshot_last_good(&last_good).unwrap();
+
+        // Одноименная библиотека в другой директории, данные плагина в поддиректории и новый плагин
+        std::fs::write(&local, "local, broken").unwrap();
+        std::fs::write(&data, "{\"broken\": true}").unwrap();
+        let added = dir.write(&format!("plugins/{}", library("new_plugin")), "new");
+
+        journal.roll_back(&journal.current_plugins(), &last_good).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&shipped).unwrap(), "shipped");
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "local");
+        assert_eq!(std::fs::read_to_string(&data).unwrap(), "{}");
+        assert!(!added.exists());
+        assert_eq!(journal.current_plugins(), last_good);
+
+        let quarantine = dir.path().join("state").join(quarantine::QUARANTINE_DIR);
+        let batch = std::fs::read_dir(quarantine).unwrap().next().unwrap().unwrap().path();
+        assert_eq!(std::fs::read_to_string(batch.join("0").join(library("new_plugin"))).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(batch.join("1").join(library("x_plugin"))).unwrap(), "local, broken");
+        let quarantined_data = batch.join("0/foo/data/config.json");
+        assert_eq!(std::fs::read_to_string(quarantined_data).unwrap(), "{\"broken\": true}");
+    }
+
+    #[test]
+    fn roll_back_activates_last_good_store_version() {
+        let dir = TempDir::new("roll-back-store");
+        let plugins_dir = dir.path().join("plugins");
+        let store = PluginStore::in_dir(&plugins_dir, LibraryNaming::default());
+        let plugin_dirs = std::slice::from_ref(&plugins_dir);
+        let journal = StartJournal::new(&dir.path().join("state"), plugin_dirs, &LibraryNaming::default(), Some(store), policy())
+            .unwrap();
+        let store = journal.store.as_ref().unwrap();
+
+        let file = dir.write(&format!("upload/{}", library("foo_plugin")), "good");
+        store.install(&file, Some("1")).unwrap();
+        store.activate("foo_plugin", "1").unwrap();
+        let last_good = journal.current_plugins();
+        journal.snapshot_last_good(&last_good).unwrap();
+
+        std::fs::write(&file, "broken").unwrap();
+        store.install(&file, Some("2")).unwrap();
+        store.activate("foo_plugin", "2").unwrap();
+
+        journal.roll_back(&journal.current_plugins(), &last_good).unwrap();
+
+        // Ссылка остается ссылкой хранилища и указывает на рабочую версию
+        let link = plugins_dir.join(library("foo_plugin"));
+        let target = std::fs::read_link(&link).unwrap();
+        assert_eq!(store::release_of(&target), Some(("foo_plugin".to_string(), "1".to_string())));
+        assert_eq!(std::fs::read_to_string(&link).unwrap(), "good");
+        assert_eq!(journal.current_plugins(), last_good);
+    }
+}
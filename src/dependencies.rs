@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::library::{LibraryNaming, SERVICE_SUFFIX};
+use crate::manifest::Manifest;
+
+/// Библиотека в графе зависимостей
+#[derive(Debug, Clone)]
+struct Library {
+    /// Имя из манифеста или имя файла без префикса и окончания, см. [`LibraryNaming::name_and_suffix`]
+    name: String,
+    is_service: bool,
+    manifest: Option<Manifest>,
+}
+
+impl Library {
+    fn load(path: &Path, source: &Path, naming: &LibraryNaming) -> Self {
+        let (stem, suffix) = naming.name_and_suffix(path).unwrap_or_default();
+        let is_service = suffix == SERVICE_SUFFIX;
+
+        let manifest = Manifest::load(source).unwrap_or_else(|reason| {
+            warn!("Ignoring plugin manifest: {}", reason);
+            None
+        });
+        let name = match &manifest {
+            Some(manifest) => manifest.name.clone(),
+            None => stem.to_string(),
+        };
+
+        Self { name, is_service, manifest }
+    }
+
+    /// Зависит ли библиотека от сервиса
+    fn requires(&self, service: &str) -> bool {
+        self.manifest.as_ref().is_some_and(|manifest| manifest.services.contains_key(service))
+    }
+}
+
+/// Граф зависимостей плагинов от сервисов по их манифестам
+///
+/// Плагин объявляет сервисы в `[services]` манифеста: имя сервиса → требование к версии.
+/// Сервис без манифеста подходит только под требование `*`.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    libraries: BTreeMap<PathBuf, Library>,
+    naming: LibraryNaming,
+}
+
+impl DependencyGraph {
+    /// Граф по текущему содержимому директорий плагинов
+    pub fn scan(plugin_dirs: &[PathBuf], naming: &LibraryNaming) -> Self {
+        let libraries = crate::watcher::scan(plugin_dirs, naming)
+            .into_keys()
+            .map(|path| {
+                let library = Library::load(&path, &path, naming);
+                (path, library)
+            })
+            .collect();
+        Self { libraries, naming: naming.clone() }
+    }
+
+    /// Граф, в котором библиотека `path` заменена файлом `source` (например, версией из хранилища)
+    pub fn with_library(mut self, path: &Path, source: &Path) -> Self {
+        let library = Library::load(path, source, &self.naming);
+        self.libraries.insert(path.to_path_buf(), library);
+        self
+    }
+
+    /// Проверка изменения библиотек `changed` по графу `previous`, построенному до него
+    ///
+    /// Возвращает затронутые плагины или список плагинов, которые изменение оставляет
+    /// без нужных сервисов. Плагин, зависимости которого не выполнялись и до изменения,
+    /// изменение других библиотек не блокирует.
+    pub fn check_change(&self, previous: &Self, changed: &[PathBuf]) -> Result<BTreeSet<PathBuf>, Vec<(PathBuf, String)>> {
+        let affected = self.affected(previous, changed);
+
+        let broken: Vec<(PathBuf, String)> = affected
+            .iter()
+            .filter(|plugin| changed.contains(plugin) || previous.check(plugin).is_ok())
+            .filter_map(|plugin| Some((plugin.clone(), self.check(plugin).err()?)))
+            .collect();
+
+        if broken.is_empty() {
+            Ok(affected)
+        } else {
+            Err(broken)
+        }
+    }
+
+    /// Проверка, что все сервисы, которые требует плагин, есть и подходят по версии
+    fn check(&self, plugin: &Path) -> Result<(), String> {
+        let Some(manifest) = self.libraries.get(plugin).and_then(|library| library.manifest.as_ref()) else {
+            return Ok(());
+        };
+
+        let mut problems = Vec::new();
+        for (service, requirement) in &manifest.services {
+            let provided = self.libraries.values().filter(|library| library.is_service && library.name == *service);
+            let versions: Vec<_> = provided.map(|library| library.manifest.as_ref().map(|m| &m.version)).collect();
+
+            if versions.is_empty() {
+                problems.push(format!("requires service {} {}, which is missing", service, requirement));
+            } else if !versions.iter().any(|version| match version {
+                Some(version) => requirement.matches(version),
+                None => requirement.comparators.is_empty(),
+            }) {
+                let versions: Vec<String> = versions
+                    .iter()
+                    .map(|version| version.map_or_else(|| "unversioned".to_string(), |v| v.to_string()))
+                    .collect();
+                problems.push(format!(
+                    "requires service {} {}, found incompatible {}",
+                    service,
+                    requirement,
+                    versions.join(", ")
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} {}", manifest, problems.join("; ")))
+        }
+    }
+
+    /// Плагины, которых касается изменение библиотек `changed`
+    ///
+    /// Это сами измененные плагины и плагины, зависящие от измененных сервисов.
+    /// Имена удаленных и переименованных сервисов берутся из графа `previous`,
+    /// построенного до изменения.
+    fn affected(&self, previous: &Self, changed: &[PathBuf]) -> BTreeSet<PathBuf> {
+        let mut affected = BTreeSet::new();
+        let mut services = BTreeSet::new();
+
+        for path in changed {
+            for library in [previous.libraries.get(path), self.libraries.get(path)].into_iter().flatten() {
+                if library.is_service {
+                    services.insert(library.name.clone());
+                } else if self.libraries.contains_key(path) {
+                    affected.insert(path.clone());
+                }
+            }
+        }
+
+        for (path, library) in &self.libraries {
+            if !library.is_service && services.iter().any(|service| library.requires(service)) {
+                affected.insert(path.clone());
+            }
+        }
+
+        affected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{library, TempDir};
+
+    /// Директория с сервисом `db` 1.2.0 и плагином `foo`, которому нужен `db ^1.1`
+    fn plugins() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new("dependencies");
+        let service = dir.write(&library("db_service"), "");
+        dir.write("db_service.toml", "name = \"db\"\nversion = \"1.2.0\"\n");
+        let plugin = dir.write(&library("foo_plugin"), "");
+        dir.write("foo_plugin.toml", "name = \"foo\"\nversion = \"1.0.0\"\n\n[services]\ndb = \"^1.1\"\n");
+        (dir, service, plugin)
+    }
+
+    fn scan(dir: &TempDir) -> DependencyGraph {
+        DependencyGraph::scan(&[dir.path().to_path_buf()], &LibraryNaming::default())
+    }
+
+    fn broken_plugins(result: Result<BTreeSet<PathBuf>, Vec<(PathBuf, String)>>, problem: &str) -> Vec<PathBuf> {
+        let broken = result.unwrap_err();
+        assert!(broken.iter().all(|(_, reason)| reason.contains(problem)), "{:?}", broken);
+        broken.into_iter().map(|(plugin, _)| plugin).collect()
+    }
+
+    #[test]
+    fn compatible_service_update_affects_dependents() {
+        let (dir, service, plugin) = plugins();
+        let previous = scan(&dir);
+        dir.write("db_service.toml", "name = \"db\"\nversion = \"1.3.0\"\n");
+
+        let affected = scan(&dir).check_change(&previous, std::slice::from_ref(&service)).unwrap();
+        assert_eq!(affected, BTreeSet::from([plugin]));
+    }
+
+    #[test]
+    fn new_plugin_with_missing_service_is_rejected() {
+        let (dir, _, _) = plugins();
+        let previous = scan(&dir);
+        let added = dir.write(&library("bar_plugin"), "");
+        dir.write("bar_plugin.toml", "name = \"bar\"\nversion = \"1.0.0\"\n\n[services]\ncache = \"*\"\n");
+
+        let result = scan(&dir).check_change(&previous, std::slice::from_ref(&added));
+        assert_eq!(broken_plugins(result, "missing"), vec![added]);
+    }
+
+    #[test]
+    fn incompatible_service_update_is_rejected() {
+        let (dir, service, plugin) = plugins();
+        let previous = scan(&dir);
+        dir.write("db_service.toml", "name = \"db\"\nversion = \"2.0.0\"\n");
+
+        let result = scan(&dir).check_change(&previous, &[service]);
+        assert_eq!(broken_plugins(result, "incompatible 2.0.0"), vec![plugin]);
+    }
+
+    #[test]
+    fn removed_service_is_rejected() {
+        let (dir, service, plugin) = plugins();
+        let previous = scan(&dir);
+        std::fs::remove_file(&service).unwrap();
+        std::fs::remove_file(dir.path().join("db_service.toml")).unwrap();
+
+        let result = scan(&dir).check_change(&previous, &[service]);
+        assert_eq!(broken_plugins(result, "missing"), vec![plugin]);
+    }
+
+    #[test]
+    fn services_are_named_without_library_prefix() {
+        let dir = TempDir::new("dependencies-prefix");
+        let naming = LibraryNaming { prefixes: vec![String::new(), "lib".to_string()], ..LibraryNaming::default() };
+        let service = dir.write(&format!("lib{}", library("db_service")), "");
+        dir.write(&library("foo_plugin"), "");
+        dir.write("foo_plugin.toml", "name = \"foo\"\nversion = \"1.0.0\"\n\n[services]\ndb = \"*\"\n");
+
+        let graph = DependencyGraph::scan(&[dir.path().to_path_buf()], &naming);
+        assert!(graph.libraries[&service].is_service);
+        assert_eq!(graph.libraries[&service].name, "db");
+        assert!(graph.libraries.keys().all(|plugin| graph.check(plugin).is_ok()));
+    }
+}
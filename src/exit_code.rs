@@ -0,0 +1,107 @@
+//! Коды выхода AniSystemd
+//!
+//! | Код | Причина                                   |
+//! |-----|-------------------------------------------|
+//! | 0   | штатная остановка (SIGTERM, SIGINT)       |
+//! | 70  | ошибка бота во время работы (EX_SOFTWARE) |
+//! | 75  | запрошен перезапуск (EX_TEMPFAIL)         |
+//! | 78  | ошибка конфигурации (EX_CONFIG)           |
+//! | 79  | не удалось загрузить плагины              |
+//!
+//! Рекомендуемые настройки unit-файла:
+//!
+//! ```ini
+//! Restart=on-failure
+//! # Перезапуск по изменению плагинов, даже если Restart= его не покрывает
+//! RestartForceExitStatus=75
+//! # Без исправления конфигурации перезапуск не поможет
+//! RestartPreventExitStatus=78
+//! ```
+
+use std::fmt;
+use tracing::{error, info, warn};
+
+/// Причина завершения процесса
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Штатная остановка по запросу оператора
+    Clean,
+    /// Бот завершился с ошибкой во время работы
+    BotError,
+    /// Процесс завершается, чтобы systemd перезапустил его с новыми плагинами или конфигурацией
+    RestartRequested,
+    /// Некорректная конфигурация или окружение
+    ConfigError,
+    /// Не удалось загрузить плагины при создании бота
+    PluginLoadFailure,
+}
+
+impl ExitReason {
+    pub fn code(self) -> u8 {
+        match self {
+            ExitReason::Clean => 0,
+            ExitReason::BotError => 70,
+            ExitReason::RestartRequested => 75,
+            ExitReason::ConfigError => 78,
+            ExitReason::PluginLoadFailure => 79,
+        }
+    }
+
+    /// Записать причину в лог и сообщить код systemd через `EXIT_STATUS=`
+    pub fn report(self) {
+        info!("Exiting with code {} ({})", self.code(), self);
+
+        #[cfg(target_os = "linux")]
+        {
+            let state = libsystemd::daemon::NotifyState::Other(format!("EXIT_STATUS={}", self.code()));
+            if let Err(e) = libsystemd::daemon::notify(false, &[state]) {
+                warn!("Failed to send systemd EXIT_STATUS notification: {}", e);
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ExitReason::Clean => "clean stop",
+            ExitReason::BotError => "bot runtime error",
+            ExitReason::RestartRequested => "restart requested",
+            ExitReason::ConfigError => "configuration error",
+            ExitReason::PluginLoadFailure => "plugin load failure",
+        };
+        f.write_str(description)
+    }
+}
+
+impl From<ExitReason> for std::process::ExitCode {
+    fn from(reason: ExitReason) -> Self {
+        std::process::ExitCode::from(reason.code())
+    }
+}
+
+/// Ошибка, которая завершает процесс с определенным кодом
+#[derive(Debug)]
+pub struct Failure {
+    pub reason: ExitReason,
+    pub error: anyhow::Error,
+}
+
+impl Failure {
+    /// Записать ошибку в лог и вернуть причину завершения
+    pub fn log(self) -> ExitReason {
+        error!("{}: {:?}", self.reason, self.error);
+        self.reason
+    }
+}
+
+/// Привязка ошибки к коду выхода
+pub trait ExitContext<T> {
+    fn exit_reason(self, reason: ExitReason) -> Result<T, Failure>;
+}
+
+impl<T, E: Into<anyhow::Error>> ExitContext<T> for Result<T, E> {
+    fn exit_reason(self, reason: ExitReason) -> Result<T, Failure> {
+        self.map_err(|e| Failure { reason, error: e.into() })
+    }
+}
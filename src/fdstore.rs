@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_int, CStr};
+use std::os::fd::{AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Дескрипторы, которые нужно передать в хранилище systemd при перезапуске: имя → копия дескриптора
+static REGISTRY: Mutex<Vec<(String, OwnedFd)>> = Mutex::new(Vec::new());
+
+/// Зарегистрировать сокет для сохранения в хранилище дескрипторов systemd
+///
+/// Сохраняется копия дескриптора (`dup`), поэтому вызывающий может закрыть свой.
+/// После перезапуска сохраненные сокеты возвращаются через `LISTEN_FDS` и снова
+/// публикуются в [`crate::activation::SOCKETS_ENV`] под тем же именем.
+pub fn register(name: &str, fd: BorrowedFd<'_>) -> std::io::Result<()> {
+    if name.is_empty() || name.contains([',', '=', ':']) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid name {:?}", name)));
+    }
+    if !is_socket(fd.as_raw_fd()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a socket"));
+    }
+
+    let copy = fd.try_clone_to_owned()?;
+    info!("Registered descriptor {} as {:?} for systemd fd store", fd.as_raw_fd(), name);
+    REGISTRY.lock().unwrap().push((name.to_string(), copy));
+    Ok(())
+}
+
+/// Регистрация сокета из бота и плагинов: `anisystemd_fdstore_register("http", fd)`
+///
+/// Плагины находят функцию через `dlsym(RTLD_DEFAULT, ...)`: символ экспортируется
+/// из исполняемого файла (см. `build.rs`). Возвращает 0 или `errno` ошибки.
+///
+/// # Safety
+///
+/// `name` — указатель на строку, завершенную нулем, `fd` — открытый дескриптор,
+/// который не закрывается во время вызова.
+#[no_mangle]
+pub unsafe extern "C" fn anisystemd_fdstore_register(name: *const c_char, fd: c_int) -> c_int {
+    if name.is_null() || fd < 0 {
+        return libc::EINVAL;
+    }
+    // SAFETY: вызывающий гарантирует строку, завершенную нулем
+    let Ok(name) = unsafe { CStr::from_ptr(name) }.to_str() else {
+        return libc::EINVAL;
+    };
+    // SAFETY: вызывающий гарантирует, что дескриптор открыт на время вызова
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+
+    match register(name, fd) {
+        Ok(()) => 0,
+        Err(e) => {
+            warn!("Failed to register descriptor {} as {:?} for systemd fd store: {}", fd.as_raw_fd(), name, e);
+            e.raw_os_error().unwrap_or(libc::EINVAL)
+        }
+    }
+}
+
+/// Передать зарегистрированные дескрипторы в хранилище systemd перед перезапуском
+///
+/// Требует `FileDescriptorStoreMax=` в unit-файле. Перед сохранением старые записи
+/// с тем же именем удаляются, чтобы дескрипторы не копились от перезапуска к перезапуску.
+pub fn store() {
+    let registry = REGISTRY.lock().unwrap();
+
+    let mut by_name: BTreeMap<&str, Vec<RawFd>> = BTreeMap::new();
+    for (name, fd) in registry.iter() {
+        by_name.entry(name).or_default().push(fd.as_raw_fd());
+    }
+
+    for (name, fds) in by_name {
+        push(name, &fds);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn push(name: &str, fds: &[RawFd]) {
+    use libsystemd::daemon::{notify, notify_with_fds, NotifyState};
+
+    let remove = [NotifyState::FdstoreRemove, NotifyState::Fdname(name.to_string())];
+    if let Err(e) = notify(false, &remove) {
+        warn!("Failed to clear systemd fd store entry {:?}: {}", name, e);
+    }
+
+    let store = [NotifyState::Fdstore, NotifyState::Fdname(name.to_string())];
+    match notify_with_fds(false, &store, fds) {
+        Ok(_) => info!("Stored {} descriptor(s) as {:?} in systemd fd store", fds.len(), name),
+        Err(e) => warn!("Failed to store descriptors {:?} in systemd fd store: {}", name, e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn push(name: &str, _fds: &[RawFd]) {
+    warn!("systemd fd store is not available, descriptors {:?} will be lost on restart", name);
+}
+
+/// Открытый сокет: остальные дескрипторы следующий запуск не принял бы
+fn is_socket(fd: RawFd) -> bool {
+    socket_id(fd).is_some()
+}
+
+/// Устройство и inode открытого сокета, `None` — дескриптор закрыт или не сокет
+///
+/// У копий одного сокета (`dup`, хранилище systemd) они совпадают.
+pub fn socket_id(fd: RawFd) -> Option<(libc::dev_t, libc::ino_t)> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: fstat только записывает в переданную структуру и ничего не меняет
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } < 0 {
+        return None;
+    }
+    // SAFETY: fstat завершился успешно и заполнил структуру
+    let stat = unsafe { stat.assume_init() };
+    (stat.st_mode & libc::S_IFMT == libc::S_IFSOCK).then_some((stat.st_dev, stat.st_ino))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn registers_only_sockets() {
+        let (socket, _peer) = UnixStream::pair().unwrap();
+        register("test-socket", socket.as_fd()).unwrap();
+        assert!(REGISTRY.lock().unwrap().iter().any(|(name, _)| name == "test-socket"));
+
+        let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        assert!(register("test-file", file.as_fd()).is_err());
+        assert!(register("bad=name", socket.as_fd()).is_err());
+    }
+
+    #[test]
+    fn copies_of_a_socket_share_its_id() {
+        let (socket, peer) = UnixStream::pair().unwrap();
+        let copy = socket.try_clone().unwrap();
+        assert_eq!(socket_id(socket.as_raw_fd()), socket_id(copy.as_raw_fd()));
+        assert_ne!(socket_id(socket.as_raw_fd()), socket_id(peer.as_raw_fd()));
+    }
+}
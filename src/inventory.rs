@@ -0,0 +1,208 @@
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+use crate::checksum::{sha256_file, sha256_tree};
+use crate::library::LibraryNaming;
+use crate::manifest::Manifest;
+use crate::quarantine;
+use crate::signature::TrustPolicy;
+use crate::watcher::ChangeSet;
+
+/// Длина сокращенного хеша в статусе
+const SHORT_HASH_LEN: usize = 12;
+
+/// Содержимое и версия библиотеки плагина
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginRecord {
+    /// Имя из манифеста или имя файла без расширения
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub sha256: Option<String>,
+    /// Версия из манифеста
+    pub version: Option<String>,
+    /// Хеш содержимого плагина: библиотеки или всей его поддиректории, см. [`content_hash`]
+    pub content: Option<String>,
+}
+
+impl PluginRecord {
+    /// Запись о библиотеке, `None` — библиотеки нет
+    fn read(library: &Path, plugin_dirs: &[PathBuf]) -> Option<Self> {
+        let metadata = std::fs::metadata(library).ok()?;
+
+        let sha256 = sha256_file(library)
+            .map_err(|e| warn!("Failed to hash plugin {:?}: {}", library, e))
+            .ok();
+        let manifest = Manifest::load(library).unwrap_or_else(|reason| {
+            warn!("Ignoring plugin manifest: {}", reason);
+            None
+        });
+        let name = match &manifest {
+            Some(manifest) => manifest.name.clone(),
+            None => library.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+        };
+        let content = match plugin_subdir(library, plugin_dirs) {
+            Some(_) => content_hash(library, plugin_dirs),
+            None => sha256.clone(),
+        };
+
+        Some(Self {
+            name,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            sha256,
+            version: manifest.map(|manifest| manifest.version.to_string()),
+            content,
+        })
+    }
+
+    /// Одинаковы ли содержимое и версия плагина (размер и время изменения не учитываются)
+    pub fn same_content(&self, other: &Self) -> bool {
+        self.sha256 == other.sha256 && self.version == other.version && self.content == other.content
+    }
+
+    /// Краткое описание для статуса: `имя версия sha256`
+    fn describe(&self) -> String {
+        let mut described = self.name.clone();
+        if let Some(version) = &self.version {
+            described.push_str(&format!(" {}", version));
+        }
+        if let Some(sha256) = &self.sha256 {
+            described.push_str(&format!(" {}", &sha256[..SHORT_HASH_LEN.min(sha256.len())]));
+        }
+        described
+    }
+}
+
+/// Набор плагинов, с которыми работает бот
+///
+/// Снимается при запуске до загрузки бота и служит точкой отсчета для изменений:
+/// файл, содержимое которого совпадает с этим набором, изменением не считается.
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    plugin_dirs: Vec<PathBuf>,
+    plugins: BTreeMap<PathBuf, PluginRecord>,
+}
+
+impl Inventory {
+    pub fn scan(plugin_dirs: &[PathBuf], naming: &LibraryNaming) -> Self {
+        let plugins = crate::watcher::scan(plugin_dirs, naming)
+            .into_keys()
+            .filter_map(|library| Some((library.clone(), PluginRecord::read(&library, plugin_dirs)?)))
+            .collect();
+        Self { plugin_dirs: plugin_dirs.to_vec(), plugins }
+    }
+
+    pub fn get(&self, library: &Path) -> Option<&PluginRecord> {
+        self.plugins.get(library)
+    }
+
+    /// Текущее состояние библиотеки на диске
+    pub fn read(&self, library: &Path) -> Option<PluginRecord> {
+        PluginRecord::read(library, &self.plugin_dirs)
+    }
+
+    /// Чем набор `current` отличается от этого: новые, измененные по содержимому и удаленные библиотеки
+    pub fn changes(&self, current: &Inventory) -> ChangeSet {
+        let mut changes = ChangeSet::default();
+        for (library, record) in &current.plugins {
+            match self.plugins.get(library) {
+                None => changes.added.push(library.clone()),
+                Some(previous) if !previous.same_content(record) => changes.modified.push(library.clone()),
+                Some(_) => {}
+            }
+        }
+        changes.removed = self
+            .plugins
+            .keys()
+            .filter(|library| !current.plugins.contains_key(*library))
+            .cloned()
+            .collect();
+        changes
+    }
+
+    /// Хеши содержимого плагинов, с которыми мониторинг сравнивает изменения
+    pub fn baseline(&self) -> HashMap<PathBuf, String> {
+        self.plugins
+            .iter()
+            .filter_map(|(library, record)| Some((library.clone(), record.content.clone()?)))
+            .collect()
+    }
+
+    /// Описания плагинов для статуса
+    pub fn describe(&self) -> Vec<String> {
+        let mut described: Vec<String> = self.plugins.values().map(PluginRecord::describe).collect();
+        described.sort();
+        described
+    }
+
+    /// Перенос в карантин библиотек, не прошедших проверку подписи по политике доверия
+    ///
+    /// Отклоненное мониторингом изменение переносится в карантин, но библиотека могла
+    /// попасть в директорию и пока демон не работал, поэтому набор проверяется целиком.
+    /// Демон при этом не завершается, иначе systemd перезапускал бы его с той же
+    /// библиотекой снова и снова. Ошибка — библиотеку не удалось убрать, и ее загрузил бы бот.
+    /// Возвращает перенесенные библиотеки.
+    pub fn quarantine_untrusted(&mut self, trust: &TrustPolicy, state_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let untrusted: Vec<(PathBuf, String)> = self
+            .plugins
+            .keys()
+            .filter_map(|library| trust.verify(library).err().map(|reason| (library.clone(), reason)))
+            .collect();
+
+        let mut quarantined = Vec::new();
+        for (library, reason) in untrusted {
+            // Библиотека уже ушла в карантин вместе с поддиректорией другой библиотеки
+            if !library.exists() {
+                continue;
+            }
+            error!("Plugin {:?} rejected by trust policy: {}", library, reason);
+            quarantine::isolate(&library, &self.plugin_dirs, state_dir)
+                .with_context(|| format!("Failed to quarantine untrusted plugin {:?}", library))?;
+            quarantined.push(library);
+        }
+
+        self.plugins.retain(|library, _| library.exists());
+        Ok(quarantined)
+    }
+
+    /// Записать набор плагинов в журнал
+    pub fn log(&self) {
+        info!("Plugin inventory: {} libraries in {:?}", self.plugins.len(), self.plugin_dirs);
+        for (library, record) in &self.plugins {
+            let modified = record
+                .modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_secs());
+            info!(
+                event = "plugin_inventory",
+                path = %library.display(),
+                size = record.size,
+                modified,
+                sha256 = record.sha256.as_deref().unwrap_or("-"),
+                version = record.version.as_deref().unwrap_or("-"),
+                "Plugin {} ({})",
+                library.display(),
+                record.describe()
+            );
+        }
+    }
+}
+
+/// Хеш содержимого плагина: SHA-256 библиотеки в директории плагинов или всей
+/// поддиректории `plugins/<name>/`, если плагин лежит в ней вместе с данными
+pub fn content_hash(library: &Path, plugin_dirs: &[PathBuf]) -> Option<String> {
+    let hash = match plugin_subdir(library, plugin_dirs) {
+        Some(dir) => sha256_tree(dir),
+        None => sha256_file(library),
+    };
+    hash.map_err(|e| warn!("Failed to hash plugin {:?}: {}", library, e)).ok()
+}
+
+/// Поддиректория плагина, если библиотека лежит не прямо в директории плагинов
+fn plugin_subdir<'a>(library: &'a Path, plugin_dirs: &[PathBuf]) -> Option<&'a Path> {
+    library.parent().filter(|parent| !plugin_dirs.iter().any(|dir| dir == parent))
+}
@@ -0,0 +1,167 @@
+use std::path::Path;
+
+/// Расширения динамических библиотек всех поддерживаемых платформ
+const KNOWN_EXTENSIONS: &[&str] = &["so", "dll", "dylib"];
+
+/// Окончание имени библиотеки сервиса, остальные окончания — плагины
+pub const SERVICE_SUFFIX: &str = "_service";
+
+/// Правила, по которым файл считается библиотекой плагина или сервиса
+///
+/// Имя плагина — имя файла без префикса и расширения: `libfoo_plugin.so` → `foo_plugin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryNaming {
+    /// Расширения библиотек, которые загружаются на этой платформе, без точки
+    pub extensions: Vec<String>,
+    /// Допустимые префиксы имени файла, пустая строка — без префикса
+    pub prefixes: Vec<String>,
+    /// Окончания имени плагина
+    pub suffixes: Vec<String>,
+    /// Если список не пуст, принимаются только перечисленные плагины
+    pub allow: Vec<String>,
+    /// Плагины, которые никогда не принимаются
+    pub deny: Vec<String>,
+}
+
+impl Default for LibraryNaming {
+    /// Именование динамических библиотек текущей платформы: `foo_plugin.so`
+    /// или `libfoo_plugin.so` на Linux, `foo_plugin.dll` на Windows
+    fn default() -> Self {
+        let mut prefixes = vec![String::new()];
+        if !std::env::consts::DLL_PREFIX.is_empty() {
+            prefixes.push(std::env::consts::DLL_PREFIX.to_string());
+        }
+
+        Self {
+            extensions: vec![std::env::consts::DLL_EXTENSION.to_string()],
+            prefixes,
+            suffixes: vec!["_plugin".to_string(), SERVICE_SUFFIX.to_string()],
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+/// Чем является файл в директории плагинов
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryMatch {
+    /// Библиотека плагина или сервиса, которую можно загрузить
+    Library,
+    /// Похожа на плагин, но это библиотека другой платформы
+    Foreign,
+    /// Плагин исключен списками allow/deny
+    Denied,
+    /// Не плагин
+    Other,
+}
+
+impl LibraryNaming {
+    pub fn classify(&self, path: &Path) -> LibraryMatch {
+        let Some((name, extension)) = self.split(path) else {
+            return LibraryMatch::Other;
+        };
+        if name.is_empty() || self.suffix(name).is_none() {
+            return LibraryMatch::Other;
+        }
+
+        if !self.extensions.iter().any(|ext| ext == extension) {
+            let foreign = KNOWN_EXTENSIONS.contains(&extension);
+            return if foreign { LibraryMatch::Foreign } else { LibraryMatch::Other };
+        }
+
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|allowed| allowed == name);
+        if !allowed || self.deny.iter().any(|denied| denied == name) {
+            return LibraryMatch::Denied;
+        }
+        LibraryMatch::Library
+    }
+
+    /// Является ли файл библиотекой плагина или сервиса, которую можно загрузить
+    pub fn is_library(&self, path: &Path) -> bool {
+        self.classify(path) == LibraryMatch::Library
+    }
+
+    /// Имя библиотеки без префикса, окончания и расширения и ее окончание:
+    /// `libdb_service.so` → `("db", "_service")`, `None` — файл не библиотека
+    pub fn name_and_suffix<'a>(&self, path: &'a Path) -> Option<(&'a str, &'a str)> {
+        let (name, _) = self.split(path)?;
+        let suffix = self.suffix(name)?;
+        Some(name.split_at(name.len() - suffix.len()))
+    }
+
+    /// Имя файла без префикса и расширение
+    fn split<'a>(&self, path: &'a Path) -> Option<(&'a str, &'a str)> {
+        let (stem, extension) = path.file_name()?.to_str()?.rsplit_once('.')?;
+
+        // Самый длинный подходящий префикс, чтобы `lib` отрезался и при разрешенном пустом
+        let name = self
+            .prefixes
+            .iter()
+            .filter(|prefix| stem.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())
+            .map_or(stem, |prefix| &stem[prefix.len()..]);
+        Some((name, extension))
+    }
+
+    /// Самое длинное из окончаний, которым заканчивается имя
+    fn suffix(&self, name: &str) -> Option<&str> {
+        self.suffixes
+            .iter()
+            .filter(|suffix| name.ends_with(suffix.as_str()))
+            .max_by_key(|suffix| suffix.len())
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming() -> LibraryNaming {
+        LibraryNaming {
+            extensions: vec!["so".to_string()],
+            prefixes: vec![String::new(), "lib".to_string()],
+            suffixes: vec!["_plugin".to_string(), "_service".to_string()],
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+
+    fn classify(naming: &LibraryNaming, file: &str) -> LibraryMatch {
+        naming.classify(Path::new("/plugins").join(file).as_path())
+    }
+
+    #[test]
+    fn recognizes_libraries_with_and_without_prefix() {
+        let naming = naming();
+        assert_eq!(classify(&naming, "foo_plugin.so"), LibraryMatch::Library);
+        assert_eq!(classify(&naming, "libfoo_plugin.so"), LibraryMatch::Library);
+        assert_eq!(classify(&naming, "db_service.so"), LibraryMatch::Library);
+        assert_eq!(classify(&naming, "foo.so"), LibraryMatch::Other);
+        assert_eq!(classify(&naming, "libfoo_plugin.so.1"), LibraryMatch::Other);
+        assert_eq!(classify(&naming, "foo_plugin.toml"), LibraryMatch::Other);
+    }
+
+    #[test]
+    fn libraries_of_other_platforms_are_foreign() {
+        let naming = naming();
+        assert_eq!(classify(&naming, "foo_plugin.dll"), LibraryMatch::Foreign);
+        assert_eq!(classify(&naming, "libfoo_plugin.dylib"), LibraryMatch::Foreign);
+        assert_eq!(classify(&naming, "foo.dll"), LibraryMatch::Other);
+    }
+
+    #[test]
+    fn allow_and_deny_lists_use_names_without_prefix() {
+        let allowed = LibraryNaming { allow: vec!["foo_plugin".to_string()], ..naming() };
+        assert_eq!(classify(&allowed, "libfoo_plugin.so"), LibraryMatch::Library);
+        assert_eq!(classify(&allowed, "bar_plugin.so"), LibraryMatch::Denied);
+
+        let denied = LibraryNaming { deny: vec!["foo_plugin".to_string()], ..naming() };
+        assert_eq!(classify(&denied, "libfoo_plugin.so"), LibraryMatch::Denied);
+        assert_eq!(classify(&denied, "bar_plugin.so"), LibraryMatch::Library);
+
+        // Запрет сильнее разрешения
+        let both = LibraryNaming { deny: vec!["foo_plugin".to_string()], ..allowed };
+        assert_eq!(classify(&both, "foo_plugin.so"), LibraryMatch::Denied);
+    }
+}
@@ -1,31 +1,151 @@
-use anyhow::Result;
 use anicore::Bot;
-use notify::{Watcher, RecommendedWatcher, RecursiveMode, Event as NotifyEvent, EventKind};
-use std::path::Path;
+use std::process::ExitCode;
 use std::sync::Arc;
-use tokio::sync::Notify;
-use tracing::{info, error, warn};
+use tokio_util::sync::CancellationToken;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Загрузка переменных окружения из .env файла
-    dotenv::dotenv().ok();
+#[cfg(unix)]
+mod activation;
+mod checksum;
+mod config;
+mod crash_loop;
+mod dependencies;
+mod exit_code;
+#[cfg(unix)]
+mod fdstore;
+mod inventory;
+mod library;
+mod manifest;
+mod quarantine;
+mod reload;
+mod restart_reason;
+mod shutdown;
+mod signals;
+mod signature;
+mod status;
+mod store;
+#[cfg(test)]
+mod testing;
+mod validate;
+mod watchdog;
+mod watcher;
+
+#[cfg(unix)]
+use activation::ActivatedSockets;
+use config::{Command, Config};
+use crash_loop::StartJournal;
+use exit_code::{ExitContext, ExitReason, Failure};
+use inventory::Inventory;
+use restart_reason::RestartReason;
+use shutdown::BotExit;
+use signals::{SignalAction, Signals};
+use status::StatusReporter;
+use watchdog::Heartbeat;
+use watcher::ChangeSet;
+
+fn main() -> ExitCode {
+    // Дочерний процесс проверки плагина не запускает демон
+    if let Some(code) = validate::run_if_requested() {
+        return code;
+    }
+
+    // Статус публикуется с самого начала, чтобы `systemctl status` показывал, на каком шаге
+    // остановился запуск, в том числе до загрузки конфигурации
+    let status = StatusReporter::new();
+
+    // Загрузка переменных окружения из .env файла. Окружение меняется только здесь, пока
+    // процесс однопоточный: после запуска runtime его читают потоки tokio, бота и мониторинга
+    status.phase("Loading environment");
+    reload::load_dotenv();
 
     // Инициализация логгера (он в anicore)
     anicore::init_logger();
 
+    // Сокеты от systemd (socket activation), открыты до завершения процесса
+    #[cfg(unix)]
+    let _activated_sockets = match ActivatedSockets::receive().exit_reason(ExitReason::ConfigError) {
+        Ok(sockets) => {
+            sockets.export();
+            sockets.preserve();
+            sockets
+        }
+        Err(failure) => return exit(Err(failure), &status),
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed building the Runtime");
+    exit(runtime.block_on(run(status.clone())), &status)
+}
+
+/// Сообщить причину завершения и превратить ее в код выхода
+///
+/// Ошибка публикуется и в `STATUS=`, чтобы ее было видно в `systemctl status`.
+fn exit(result: std::result::Result<ExitReason, Failure>, status: &StatusReporter) -> ExitCode {
+    let reason = result.unwrap_or_else(|failure| {
+        status.phase(format!("Failed: {}: {:#}", failure.reason, failure.error));
+        failure.log()
+    });
+    reason.report();
+    reason.into()
+}
+
+/// Основной цикл демона, возвращает причину завершения процесса
+async fn run(status: StatusReporter) -> std::result::Result<ExitReason, Failure> {
+    // Загрузка конфигурации (командная строка, окружение, файл)
+    status.phase("Loading configuration");
+    let mut config = Config::load().exit_reason(ExitReason::ConfigError)?;
+
+    // Команды управления хранилищем плагинов выполняются вместо демона
+    if let Some(Command::Plugin(command)) = config.command.take() {
+        store::run_command(command, &config)
+            .await
+            .exit_reason(ExitReason::PluginLoadFailure)?;
+        return Ok(ExitReason::Clean);
+    }
+
     info!("AniSystemd starting...");
 
-    // Создание канала для уведомления об изменении плагинов
-    let plugin_changed = Arc::new(Notify::new());
-    let plugin_changed_clone = plugin_changed.clone();
+    // Почему завершился предыдущий процесс, чтобы связать поведение бота с обновлением плагинов
+    if let Some(previous) = RestartReason::take(&config.state_dir) {
+        previous.announce();
+    }
+
+    // Подписка на сигналы до инициализации бота, чтобы не потерять SIGTERM во время старта
+    let mut signals = Signals::new().exit_reason(ExitReason::ConfigError)?;
+
+    // Журнал запусков: при цикле падений после изменения плагинов откатываем их до мониторинга,
+    // чтобы перенос файлов не считался новым изменением
+    let journal = config.crash_loop.clone().and_then(|policy| {
+        let store = store::PluginStore::new(&config).ok();
+        let journal = StartJournal::new(&config.state_dir, &config.plugin_dirs, &config.library_naming, store, policy)
+            .map_err(|e| warn!("Start journal disabled: {:?}", e))
+            .ok()?;
+        match journal.begin() {
+            Ok(true) => status.phase("Rolled back plugins after crash loop"),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to update start journal: {:?}", e),
+        }
+        Some(Arc::new(journal))
+    });
+
+    // Плагины, с которыми запускается бот: с ними сравниваются последующие изменения
+    let mut running = Inventory::scan(&config.plugin_dirs, &config.library_naming);
+    let quarantined = match &config.trust_policy {
+        Some(trust) => running.quarantine_untrusted(trust, &config.state_dir).exit_reason(ExitReason::PluginLoadFailure)?,
+        None => Vec::new(),
+    };
+    running.log();
+    status.set_plugins(running.describe());
 
     // Запуск мониторинга плагинов
-    let plugins_dir = "./plugins";
-    start_plugin_watcher(plugins_dir, plugin_changed_clone).await?;
+    let mut plugin_changes = start_watching(&config, &status, &running).exit_reason(ExitReason::ConfigError)?;
 
     // Создание и запуск бота
-    let bot = Bot::new().await?;
+    status.phase("Starting bot");
+    let bot = Bot::new().await.exit_reason(ExitReason::PluginLoadFailure)?;
     
     // Отправка READY уведомления systemd после успешной инициализации (только на Linux)
     #[cfg(target_os = "linux")]
@@ -37,146 +157,162 @@ async fn main() -> Result<()> {
         }
     }
 
-    // Запуск watchdog в фоне (только на Linux)
-    #[cfg(target_os = "linux")]
-    let watchdog_handle = tokio::spawn(async move {
-        loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
-            if let Err(e) = libsystemd::daemon::notify(false, &[libsystemd::daemon::NotifyState::Watchdog]) {
-                warn!("Failed to send systemd WATCHDOG notification: {}", e);
-            }
-        }
-    });
-    
-    #[cfg(not(target_os = "linux"))]
-    let watchdog_handle = tokio::spawn(async {
-        // На не-Linux системах просто ждем бесконечно
-        loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
-        }
-    });
+    if quarantined.is_empty() {
+        status.phase("Running");
+    } else {
+        status.phase(format!("Running, quarantined untrusted plugins: {:?}", quarantined));
+    }
+    let status_refresh = status.spawn_refresh();
 
-    // Флаг для отслеживания изменений плагинов
-    let should_restart = Arc::new(tokio::sync::Mutex::new(false));
-    let should_restart_clone = should_restart.clone();
-    let plugin_changed_monitor = plugin_changed.clone();
+    // Запуск считается успешным, если бот проработал достаточно долго
+    let journal_handle = journal.clone().map(StartJournal::spawn_mark_good);
 
-    // Мониторинг изменений плагинов в фоне
-    tokio::spawn(async move {
-        plugin_changed_monitor.notified().await;
-        info!("Plugin change detected, will trigger restart after bot shutdown...");
-        *should_restart_clone.lock().await = true;
-    });
+    // Heartbeat задачи бота: watchdog не подтверждает живость, если поток бота заблокирован
+    let heartbeat = Heartbeat::new(watchdog::stall_threshold(config.watchdog_stall_threshold));
+
+    // Запуск watchdog в фоне (только на Linux и только если его включил systemd)
+    let watchdog_handle = watchdog::start(heartbeat.clone());
 
     // Запуск бота (блокирующий вызов)
-    // При обнаружении изменений плагинов мы завершим процесс после остановки бота
-    let bot_result = tokio::select! {
-        result = bot.start() => {
-            result
-        }
-        _ = plugin_changed.notified() => {
-            // Изменение плагинов обнаружено
-            // Bot::start() обрабатывает ctrl_c, но мы не можем отправить его напрямую
-            // Поэтому просто завершаем с кодом 0 для перезапуска systemd
-            info!("Plugin change detected, exiting for systemd restart...");
-            // Даем небольшое время на логирование
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            // Устанавливаем флаг для перезапуска
-            *should_restart.lock().await = true;
-            Ok(())
-        }
-        _ = tokio::signal::ctrl_c() => {
-            info!("Ctrl+C received, shutting down...");
-            Ok(())
+    // При обнаружении изменений плагинов или SIGHUP мы дадим боту завершить текущую работу
+    // и завершим процесс для перезапуска systemd
+    let shutdown = CancellationToken::new();
+    let bot_future = shutdown::run_bot(
+        watchdog::with_heartbeat(bot.start(), heartbeat.clone()),
+        shutdown.clone(),
+        config.drain_timeout,
+    );
+    tokio::pin!(bot_future);
+
+    let mut restart_reason = None;
+    let bot_exit = loop {
+        tokio::select! {
+            exit = &mut bot_future => {
+                break exit;
+            }
+            Some(changes) = plugin_changes.recv(), if !shutdown.is_cancelled() => {
+                info!("Plugin change detected ({}), stopping bot for systemd restart...", changes);
+                status.phase(format!("Plugin change detected, draining bot before restart: {}", changes));
+                let diff = restart_reason::diff(&running, &changes);
+                restart_reason::log(&diff);
+                restart_reason = Some(RestartReason::plugin_change(diff));
+
+                // Дескрипторы передаются в хранилище systemd до остановки бота, пока они еще открыты
+                #[cfg(unix)]
+                fdstore::store();
+                shutdown.cancel();
+            }
+            action = signals.recv() => match action {
+                // Во время остановки повторные сигналы (в том числе SIGINT, отправленный боту) игнорируются
+                SignalAction::Shutdown(_) if shutdown.is_cancelled() => {}
+                SignalAction::Shutdown(name) => {
+                    info!("{} received, shutting down...", name);
+                    status.phase(format!("Draining bot after {}", name));
+                    shutdown.cancel();
+                }
+                SignalAction::Reload if shutdown.is_cancelled() => {}
+                SignalAction::Reload => {
+                    // Перезагрузка по протоколу Type=notify-reload: RELOADING=1, перечитать
+                    // окружение и конфигурацию, перезапустить мониторинг плагинов, READY=1
+                    info!("SIGHUP received, reloading configuration and plugins...");
+                    status.phase("Reloading configuration");
+                    reload::notify_reloading();
+
+                    match reload::reload_config(&config) {
+                        Ok(new_config) => match start_watching(&new_config, &status, &running) {
+                            Ok(changes) => {
+                                config = new_config;
+                                plugin_changes = changes;
+                                status.phase("Running");
+                            }
+                            Err(e) => {
+                                error!("Failed to restart plugin watcher, keeping previous configuration: {:?}", e);
+                                status.phase("Running, reload failed");
+                            }
+                        },
+                        Err(e) => {
+                            error!("Failed to reload configuration, keeping previous configuration: {:?}", e);
+                            status.phase("Running, reload failed");
+                        }
+                    }
+
+                    reload::notify_reloaded();
+                }
+                SignalAction::DumpStatus => {
+                    info!(
+                        "Status: {}, last bot heartbeat {:?} ago, plugins directories {:?}, watcher/watchdog tracing {}, stopping {}",
+                        status.line(),
+                        heartbeat.since_last_beat(),
+                        config.plugin_dirs,
+                        signals::trace_enabled(),
+                        shutdown.is_cancelled()
+                    );
+                }
+                SignalAction::ToggleTrace => {
+                    let enabled = signals::toggle_trace();
+                    info!("Watcher/watchdog tracing {}", if enabled { "enabled" } else { "disabled" });
+                }
+            },
         }
     };
 
+    let bot_result = match bot_exit {
+        BotExit::Finished(result) | BotExit::Drained(result) => result,
+        BotExit::Aborted => Ok(()),
+    };
+
     // Остановка watchdog
-    watchdog_handle.abort();
+    if let Some(handle) = watchdog_handle {
+        handle.abort();
+    }
+    status_refresh.abort();
+    if let Some(handle) = journal_handle {
+        handle.abort();
+    }
+
+    // Причина записывается до проверки результата: бот может завершиться с ошибкой во время
+    // остановки, а systemd все равно перезапустит сервис, и запуск должен ее объявить
+    let restarting = restart_reason.is_some();
+    if let Some(reason) = restart_reason {
+        if let Err(e) = reason.save(&config.state_dir) {
+            warn!("Failed to record restart reason: {:?}", e);
+        }
+    }
 
     // Проверяем результат работы бота
     if let Err(e) = bot_result {
-        error!("Bot error: {:?}", e);
-        return Err(e.into());
+        return Err(e).exit_reason(ExitReason::BotError);
     }
 
-    // Проверяем, было ли обнаружено изменение плагинов
-    let should_restart_flag = *should_restart.lock().await;
-
     info!("AniSystemd stopping...");
-    
-    // Если обнаружено изменение плагинов, выходим с кодом 0 для перезапуска systemd
-    if should_restart_flag {
-        info!("Exiting with code 0 for systemd restart...");
-        std::process::exit(0);
-    }
-    
-    Ok(())
-}
 
-/// Запуск мониторинга директории плагинов
-async fn start_plugin_watcher(plugins_dir: &str, notify: Arc<Notify>) -> Result<()> {
-    let plugins_path = Path::new(plugins_dir);
-    
-    // Создать директорию если не существует
-    if !plugins_path.exists() {
-        tokio::fs::create_dir_all(plugins_path).await?;
-        info!("Created plugins directory: {:?}", plugins_path);
-    }
-
-    let notify_clone = notify.clone();
-    let plugins_dir_path = plugins_path.to_path_buf();
-
-    let mut watcher: RecommendedWatcher = notify::recommended_watcher(move |res: std::result::Result<NotifyEvent, notify::Error>| {
-        match res {
-            Ok(event) => {
-                // Проверяем, что это событие связано с плагинами или сервисами
-                let is_plugin_or_service = event.paths.iter().any(|path| {
-                    if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                        file_name.ends_with("_plugin.so") 
-                            || file_name.ends_with("_plugin.dll")
-                            || file_name.ends_with("_plugin.dylib")
-                            || file_name.ends_with("_service.so")
-                            || file_name.ends_with("_service.dll")
-                            || file_name.ends_with("_service.dylib")
-                    } else {
-                        false
-                    }
-                });
+    // Если обнаружено изменение плагинов, выходим с кодом, по которому systemd перезапустит сервис
+    let exit = if restarting {
+        status.phase("Restarting");
+        ExitReason::RestartRequested
+    } else {
+        status.phase("Stopped");
+        ExitReason::Clean
+    };
 
-                if is_plugin_or_service {
-                    match event.kind {
-                        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
-                            info!("Plugin/Service change detected: {:?}", event.paths);
-                            notify_clone.notify_one();
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Plugin watcher error: {:?}", e);
-            }
+    // Штатная остановка и перезапуск не считаются падением, даже если бот проработал недолго
+    if let Some(journal) = &journal {
+        if let Err(e) = journal.mark_exit(exit) {
+            warn!("Failed to update start journal: {:?}", e);
         }
-    }).map_err(|e| anyhow::anyhow!("Failed to create plugin watcher: {}", e))?;
-
-    watcher.watch(&plugins_dir_path, RecursiveMode::NonRecursive)
-        .map_err(|e| anyhow::anyhow!("Failed to watch plugins directory: {}", e))?;
+    }
+    Ok(exit)
+}
 
-    info!("Started monitoring plugins directory: {:?}", plugins_dir_path);
+/// Запуск мониторинга плагинов по текущей конфигурации
+///
+/// Изменения приходят в канал одной пачкой; в канал попадают только изменения,
+/// прошедшие проверку манифестов, подписей, зависимостей и загрузки плагинов.
+/// Содержимое плагинов сравнивается с набором `running`, с которым работает бот,
+/// поэтому изменения, которые предыдущий мониторинг не успел отправить до reload, не теряются.
+fn start_watching(config: &Config, status: &StatusReporter, running: &Inventory) -> anyhow::Result<mpsc::Receiver<ChangeSet>> {
+    let changes = watcher::start(&config.plugin_dirs, &config.watch, &config.library_naming, running)?;
 
-    // Сохранить watcher в Arc и запустить в фоне
-    let watcher_arc = Arc::new(std::sync::Mutex::new(Some(watcher)));
-    let watcher_clone = watcher_arc.clone();
-    
-    tokio::spawn(async move {
-        // Watcher будет работать пока существует
-        let _watcher = watcher_clone;
-        loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
-        }
-    });
-
-    Ok(())
+    // Перезапуск только в подписанные и совместимые плагины, которые загружаются без ошибок
+    Ok(validate::gate(changes, config, status.clone()))
 }
-
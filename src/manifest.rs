@@ -0,0 +1,122 @@
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Манифест плагина или сервиса: `foo_plugin.toml` рядом с `foo_plugin.so`
+///
+/// ```toml
+/// name = "foo"
+/// version = "1.2.0"
+/// anicore_abi = { min = 3, max = 4 }
+/// hot_reload = false
+///
+/// [services]
+/// db = "^1.1"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub name: String,
+    pub version: Version,
+    /// Поддерживаемые версии ABI anicore, включительно
+    pub anicore_abi: Option<AbiRange>,
+    /// Сервисы, от которых зависит плагин: имя → требование к версии
+    #[serde(default)]
+    pub services: BTreeMap<String, VersionReq>,
+    /// Может ли плагин перезагружаться без перезапуска процесса
+    ///
+    /// Пока только читается: anicore не умеет выгружать плагины, и любое изменение
+    /// применяется перезапуском. Значение не влияет на проверку плагина.
+    #[serde(default)]
+    pub hot_reload: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AbiRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl Manifest {
+    /// Чтение манифеста библиотеки, `None` — манифеста нет
+    pub fn load(library: &Path) -> Result<Option<Self>, String> {
+        let Some(path) = manifest_path(library) else {
+            return Ok(None);
+        };
+
+        let content = std::fs::read_to_string(&path).map_err(|e| format!("failed to read manifest {:?}: {}", path, e))?;
+        let manifest: Self = toml::from_str(&content).map_err(|e| format!("invalid manifest {:?}: {}", path, e))?;
+
+        if manifest.name.trim().is_empty() {
+            return Err(format!("manifest {:?} has an empty name", path));
+        }
+        if let Some(abi) = manifest.anicore_abi {
+            if abi.min > abi.max {
+                return Err(format!("manifest {:?} has an empty anicore_abi range {}..={}", path, abi.min, abi.max));
+            }
+        }
+
+        Ok(Some(manifest))
+    }
+
+    /// Совместим ли плагин с ABI anicore, под который собран демон
+    pub fn check_abi(&self, abi_version: u32) -> Result<(), String> {
+        match self.anicore_abi {
+            Some(abi) if !(abi.min..=abi.max).contains(&abi_version) => Err(format!(
+                "{} {} supports anicore ABI {}..={}, anicore expects {}",
+                self.name, self.version, abi.min, abi.max, abi_version
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
+/// Файл манифеста рядом с библиотекой
+///
+/// Для символической ссылки (активная версия из хранилища) манифест ищется
+/// и рядом с ссылкой, и рядом с файлом, на который она указывает.
+pub fn manifest_path(library: &Path) -> Option<PathBuf> {
+    let beside = |library: &Path| Some(library.with_extension("toml")).filter(|path| path.is_file());
+    beside(library).or_else(|| beside(&library.canonicalize().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{library, TempDir};
+
+    #[test]
+    fn loads_manifest_with_all_fields() {
+        let dir = TempDir::new("manifest");
+        let plugin = dir.write(&library("foo_plugin"), "");
+        dir.write(
+            "foo_plugin.toml",
+            "name = \"foo\"\nversion = \"1.2.0\"\nanicore_abi = { min = 3, max = 4 }\nhot_reload = true\n\n[services]\ndb = \"^1.1\"\n",
+        );
+
+        let manifest = Manifest::load(&plugin).unwrap().unwrap();
+        assert_eq!(manifest.to_string(), "foo 1.2.0");
+        assert!(manifest.hot_reload);
+        assert!(manifest.check_abi(4).is_ok());
+        assert!(manifest.check_abi(5).is_err());
+        assert_eq!(manifest.services.keys().collect::<Vec<_>>(), ["db"]);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let dir = TempDir::new("manifest");
+        let plugin = dir.write(&library("foo_plugin"), "");
+        dir.write("foo_plugin.toml", "name = \"foo\"\nversion = \"1.2.0\"\nunknown = 1\n");
+
+        assert!(Manifest::load(&plugin).is_err());
+    }
+}
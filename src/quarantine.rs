@@ -0,0 +1,144 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::signature::SIGNATURE_EXTENSION;
+
+/// Поддиректория директории состояния, в которую переносятся отклоненные плагины
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Путь плагина в копиях и карантине: `<номер директории плагинов>/<путь внутри нее>`
+///
+/// Номер директории различает одноименные плагины в разных директориях, например
+/// поставляемый и его локальную замену.
+pub fn backup_key(path: &Path, plugin_dirs: &[PathBuf]) -> Option<PathBuf> {
+    plugin_dirs
+        .iter()
+        .enumerate()
+        .find_map(|(index, dir)| Some(Path::new(&index.to_string()).join(path.strip_prefix(dir).ok()?)))
+}
+
+/// Директория карантина для плагинов, отклоненных сейчас: `<state_dir>/quarantine/<время>/`
+pub fn batch_dir(state_dir: &Path) -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    state_dir.join(QUARANTINE_DIR).join(now.to_string())
+}
+
+/// Перенести отклоненную библиотеку в карантин вместе с подписью и манифестом
+///
+/// Файлы, которые лежат рядом с библиотекой в директории плагинов, переносятся, чтобы
+/// при следующем запуске бот не загрузил библиотеку, не прошедшую проверку.
+/// Плагин в своей поддиректории переносится вместе со всей поддиректорией.
+/// Версии в хранилище (цель символической ссылки) остаются на месте.
+/// Возвращает путь библиотеки или ее поддиректории в карантине.
+pub fn isolate(library: &Path, plugin_dirs: &[PathBuf], state_dir: &Path) -> Result<PathBuf> {
+    let batch = batch_dir(state_dir);
+    let mut files = plugin_files(library, plugin_dirs).into_iter();
+    let root = files.next().ok_or_else(|| anyhow::anyhow!("{:?} does not exist", library))?;
+
+    let key = backup_key(&root, plugin_dirs)
+        .ok_or_else(|| anyhow::anyhow!("{:?} is outside of the plugins directories", library))?;
+    let target = batch.join(key);
+    move_path(&root, &target)?;
+
+    for sidecar in files {
+        let Some(key) = backup_key(&sidecar, plugin_dirs) else {
+            continue;
+        };
+        if let Err(e) = move_path(&sidecar, &batch.join(key)) {
+            warn!("Failed to quarantine {:?}: {:?}", sidecar, e);
+        }
+    }
+
+    warn!("Quarantined plugin {:?} to {:?}", root, target);
+    Ok(target)
+}
+
+/// Копия плагина в `backup_dir`, разложенном по [`backup_key`], `None` — копии нет
+pub fn backup_of(library: &Path, plugin_dirs: &[PathBuf], backup_dir: &Path) -> Option<PathBuf> {
+    let root = plugin_paths(library, plugin_dirs).into_iter().next()?;
+    Some(backup_dir.join(backup_key(&root, plugin_dirs)?)).filter(|backup| std::fs::symlink_metadata(backup).is_ok())
+}
+
+/// Восстановить плагин из копии в `backup_dir`, см. [`backup_of`]
+///
+/// Вместе с библиотекой восстанавливаются подпись и манифест, если их копии есть.
+pub fn restore(library: &Path, plugin_dirs: &[PathBuf], backup_dir: &Path) -> Result<()> {
+    for path in plugin_paths(library, plugin_dirs) {
+        let Some(backup) = backup_key(&path, plugin_dirs).map(|key| backup_dir.join(key)) else {
+            continue;
+        };
+        if backup.exists() {
+            copy_path(&backup, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Существующие файлы плагина, которые переносятся и восстанавливаются вместе, см. [`plugin_paths`]
+pub fn plugin_files(library: &Path, plugin_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    plugin_paths(library, plugin_dirs)
+        .into_iter()
+        .filter(|path| std::fs::symlink_metadata(path).is_ok())
+        .collect()
+}
+
+/// Файлы плагина: вся поддиректория `plugins/<name>/` или библиотека, а за ней
+/// подпись и манифест рядом с ней
+fn plugin_paths(library: &Path, plugin_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    match library.parent() {
+        Some(parent) if !plugin_dirs.iter().any(|dir| dir == parent) => vec![parent.to_path_buf()],
+        _ => {
+            let mut signature = library.as_os_str().to_os_string();
+            signature.push(format!(".{}", SIGNATURE_EXTENSION));
+            vec![library.to_path_buf(), PathBuf::from(signature), library.with_extension("toml")]
+        }
+    }
+}
+
+/// Перенос файла или директории, в том числе между файловыми системами
+///
+/// Недостающие родительские директории цели создаются.
+pub fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    copy_path(from, to)?;
+    if std::fs::symlink_metadata(from)?.is_dir() {
+        std::fs::remove_dir_all(from)?;
+    } else {
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Копирование файла или директории целиком с сохранением времени изменения
+///
+/// Недостающие родительские директории цели создаются. Символические ссылки разыменовываются: копия не зависит от версий в хранилище,
+/// которые могут быть удалены. Время изменения сохраняется, чтобы отпечаток
+/// восстановленного плагина совпал с отпечатком исходного.
+pub fn copy_path(from: &Path, to: &Path) -> Result<()> {
+    let metadata = std::fs::metadata(from)?;
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if metadata.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(from, to)?;
+    }
+
+    // Время директории выставляется последним: копирование содержимого его меняет
+    let copy = if metadata.is_dir() { std::fs::File::open(to)? } else { std::fs::File::options().write(true).open(to)? };
+    copy.set_modified(metadata.modified()?)?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+use crate::config::Config;
+
+/// Переменные, которые при запуске были взяты из `.env`
+static DOTENV_KEYS: OnceLock<BTreeSet<String>> = OnceLock::new();
+
+/// Загрузить переменные из `.env` при запуске
+///
+/// Как и `dotenv::dotenv()`, заданные переменные окружения (например, из unit-файла)
+/// не заменяются. Взятые из файла переменные запоминаются для [`reload_config`].
+/// Вызывается, пока процесс однопоточный: окружение меняется только здесь.
+pub fn load_dotenv() {
+    let mut keys = BTreeSet::new();
+    for (key, value) in read_dotenv().unwrap_or_default() {
+        if std::env::var_os(&key).is_none() {
+            std::env::set_var(&key, value);
+            keys.insert(key);
+        }
+    }
+    let _ = DOTENV_KEYS.set(keys);
+}
+
+/// Перечитать окружение и конфигурацию по `systemctl reload`
+///
+/// Результат совпадает с новым запуском: значения из `.env` заменяют только переменные,
+/// которые при запуске были взяты из файла, и новые, а переменная, удаленная из файла,
+/// считается незаданной. Окружение процесса не меняется: значения передаются только
+/// в загрузку конфигурации. Параметры, которые используются только при запуске бота,
+/// применяются после следующего перезапуска.
+pub fn reload_config(current: &Config) -> Result<Config> {
+    let dotenv = read_dotenv()?;
+    let loaded = DOTENV_KEYS.get().cloned().unwrap_or_default();
+
+    let mut env: BTreeMap<String, Option<String>> = loaded.iter().map(|key| (key.clone(), None)).collect();
+    for (key, value) in dotenv {
+        if loaded.contains(&key) || std::env::var_os(&key).is_none() {
+            env.insert(key, Some(value));
+        }
+    }
+
+    let config = Config::load_with(&env)?;
+
+    if config.drain_timeout != current.drain_timeout
+        || config.watchdog_stall_threshold != current.watchdog_stall_threshold
+    {
+        warn!("Drain timeout and watchdog stall threshold changes take effect after restart");
+    }
+
+    info!("Configuration reloaded");
+    Ok(config)
+}
+
+/// Переменные из `.env`, пустой набор — файла нет
+fn read_dotenv() -> Result<BTreeMap<String, String>> {
+    #[allow(deprecated)]
+    match dotenv::dotenv_iter() {
+        Ok(vars) => Ok(vars.collect::<Result<_, _>>()?),
+        Err(e) if e.not_found() => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Начало перезагрузки по протоколу `Type=notify-reload` (только на Linux)
+pub fn notify_reloading() {
+    #[cfg(target_os = "linux")]
+    {
+        use libsystemd::daemon::{notify, NotifyState};
+
+        let states = [
+            NotifyState::Reloading,
+            NotifyState::Other(format!("MONOTONIC_USEC={}", monotonic_usec())),
+        ];
+        if let Err(e) = notify(false, &states) {
+            warn!("Failed to send systemd RELOADING notification: {}", e);
+        }
+    }
+}
+
+/// Окончание перезагрузки (только на Linux)
+pub fn notify_reloaded() {
+    #[cfg(target_os = "linux")]
+    if let Err(e) = libsystemd::daemon::notify(false, &[libsystemd::daemon::NotifyState::Ready]) {
+        warn!("Failed to send systemd READY notification: {}", e);
+    }
+}
+
+/// Текущее значение CLOCK_MONOTONIC в микросекундах, как его ожидает systemd
+#[cfg(target_os = "linux")]
+fn monotonic_usec() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: clock_gettime только записывает в переданную структуру
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000
+}
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::inventory::{Inventory, PluginRecord};
+use crate::watcher::ChangeSet;
+
+/// Файл с причиной последнего перезапуска в директории состояния
+const REASON_FILE: &str = "restart-reason.toml";
+
+/// Причина, уже объявленная при запуске; хранится до следующего перезапуска
+const ANNOUNCED_FILE: &str = "restart-reason.last.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Updated,
+    Removed,
+    /// Файл изменился, но содержимое и версия — нет
+    Touched,
+    /// Сама библиотека не менялась, изменился сервис, от которого она зависит
+    Affected,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Touched => "touched",
+            ChangeKind::Affected => "affected",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Изменение одной библиотеки относительно набора, с которым работал бот
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginChange {
+    pub path: PathBuf,
+    pub change: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_version: Option<String>,
+}
+
+impl PluginChange {
+    fn new(path: &Path, old: Option<&PluginRecord>, new: Option<PluginRecord>, affected: bool) -> Option<Self> {
+        let change = match (old, &new) {
+            (None, None) => return None,
+            _ if affected => ChangeKind::Affected,
+            (None, Some(_)) => ChangeKind::Added,
+            (Some(_), None) => ChangeKind::Removed,
+            (Some(old), Some(new)) if old.same_content(new) => ChangeKind::Touched,
+            (Some(_), Some(_)) => ChangeKind::Updated,
+        };
+
+        Some(Self {
+            path: path.to_path_buf(),
+            change,
+            old_sha256: old.and_then(|old| old.sha256.clone()),
+            old_version: old.and_then(|old| old.version.clone()),
+            new_sha256: new.as_ref().and_then(|new| new.sha256.clone()),
+            new_version: new.and_then(|new| new.version),
+        })
+    }
+
+    /// Структурированное событие в журнал
+    fn log(&self) {
+        info!(
+            event = "plugin_change",
+            path = %self.path.display(),
+            change = %self.change,
+            old_sha256 = self.old_sha256.as_deref().unwrap_or("-"),
+            new_sha256 = self.new_sha256.as_deref().unwrap_or("-"),
+            old_version = self.old_version.as_deref().unwrap_or("-"),
+            new_version = self.new_version.as_deref().unwrap_or("-"),
+            "Plugin {} {}",
+            self.path.display(),
+            self.change
+        );
+    }
+}
+
+/// Разница между набором плагинов, с которым работает бот, и текущими файлами
+pub fn diff(running: &Inventory, changes: &ChangeSet) -> Vec<PluginChange> {
+    let changed = changes.added.iter().chain(&changes.modified).chain(&changes.removed);
+    let changed = changed.map(|path| (path, false));
+    let affected = changes.affected.iter().map(|path| (path, true));
+
+    changed
+        .chain(affected)
+        .filter_map(|(path, affected)| PluginChange::new(path, running.get(path), running.read(path), affected))
+        .collect()
+}
+
+/// Записать изменения плагинов в журнал
+pub fn log(changes: &[PluginChange]) {
+    for change in changes {
+        change.log();
+    }
+}
+
+/// Причина перезапуска, которую объявит следующий запуск
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestartReason {
+    /// Время перезапуска (секунды UNIX)
+    pub restarted_at: u64,
+    pub reason: String,
+    #[serde(default)]
+    pub changes: Vec<PluginChange>,
+}
+
+impl RestartReason {
+    pub fn plugin_change(changes: Vec<PluginChange>) -> Self {
+        Self {
+            restarted_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            reason: "plugin change".to_string(),
+            changes,
+        }
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(state_dir)?;
+        let path = state_dir.join(REASON_FILE);
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, toml::to_string(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        info!("Restart reason recorded in {:?}", path);
+        Ok(())
+    }
+
+    /// Прочитать причину предыдущего перезапуска, если она есть, и отметить ее объявленной
+    pub fn take(state_dir: &Path) -> Option<Self> {
+        let path = state_dir.join(REASON_FILE);
+        let content = std::fs::read_to_string(&path).ok()?;
+
+        if let Err(e) = std::fs::rename(&path, state_dir.join(ANNOUNCED_FILE)) {
+            warn!("Failed to archive restart reason {:?}: {}", path, e);
+        }
+        toml::from_str(&content)
+            .map_err(|e| warn!("Ignoring corrupted restart reason {:?}: {}", path, e))
+            .ok()
+    }
+
+    /// Объявить причину при запуске
+    pub fn announce(&self) {
+        let ago = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().saturating_sub(self.restarted_at))
+            .unwrap_or(0);
+        info!(
+            event = "restart_reason",
+            reason = %self.reason,
+            changes = self.changes.len(),
+            "Restarted {}s ago because of {} ({} plugin change(s))",
+            ago,
+            self.reason,
+            self.changes.len()
+        );
+        log(&self.changes);
+    }
+}
@@ -0,0 +1,64 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Чем закончилась работа бота
+#[derive(Debug)]
+pub enum BotExit<T> {
+    /// Бот завершился сам, без запроса остановки
+    Finished(T),
+    /// Бот корректно завершил работу после запроса остановки
+    Drained(T),
+    /// Бот не уложился в таймаут и был прерван
+    Aborted,
+}
+
+/// Выполняет future бота до его завершения или до отмены `token`
+///
+/// После отмены systemd получает `STOPPING=1`, боту отправляется запрос на остановку,
+/// и у него есть `drain_timeout`, чтобы закончить текущую работу. Если бот не успел,
+/// future сбрасывается и обработчики прерываются.
+pub async fn run_bot<F: Future>(bot: F, token: CancellationToken, drain_timeout: Duration) -> BotExit<F::Output> {
+    tokio::pin!(bot);
+
+    tokio::select! {
+        output = &mut bot => return BotExit::Finished(output),
+        _ = token.cancelled() => {}
+    }
+
+    info!("Draining bot, waiting up to {:?} for in-flight work...", drain_timeout);
+    notify_stopping();
+    request_bot_stop();
+
+    match tokio::time::timeout(drain_timeout, &mut bot).await {
+        Ok(output) => {
+            info!("Bot drained");
+            BotExit::Drained(output)
+        }
+        Err(_) => {
+            warn!("Bot did not stop within {:?}, aborting", drain_timeout);
+            BotExit::Aborted
+        }
+    }
+}
+
+/// Сообщить systemd о начале остановки (только на Linux)
+fn notify_stopping() {
+    #[cfg(target_os = "linux")]
+    if let Err(e) = libsystemd::daemon::notify(false, &[libsystemd::daemon::NotifyState::Stopping]) {
+        warn!("Failed to send systemd STOPPING notification: {}", e);
+    }
+}
+
+/// Попросить бота остановиться
+///
+/// Bot::start() сам завершается по ctrl_c, поэтому процесс отправляет SIGINT самому себе.
+/// Обработчики сигналов tokio уже установлены, так что процесс от этого не завершится.
+fn request_bot_stop() {
+    #[cfg(unix)]
+    // SAFETY: raise() только ставит сигнал в очередь текущему потоку
+    if unsafe { libc::raise(libc::SIGINT) } != 0 {
+        warn!("Failed to deliver SIGINT to the bot: {}", std::io::Error::last_os_error());
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Трассировка событий мониторинга плагинов и пингов watchdog, переключается по SIGUSR2
+///
+/// Это не уровень логирования: логгер настраивает anicore, и демон не может его менять.
+/// Трассировка только добавляет в лог (на уровне info) каждое событие файловой системы
+/// в директориях плагинов и каждый отправленный `WATCHDOG=1`.
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Включена ли трассировка мониторинга и watchdog
+pub fn trace_enabled() -> bool {
+    TRACE.load(Ordering::Relaxed)
+}
+
+/// Переключить трассировку мониторинга и watchdog, возвращает новое значение
+pub fn toggle_trace() -> bool {
+    !TRACE.fetch_xor(true, Ordering::Relaxed)
+}
+
+/// Действие, которое запрошено сигналом
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// SIGTERM / SIGINT: корректная остановка
+    Shutdown(&'static str),
+    /// SIGHUP: перечитать конфигурацию и плагины
+    Reload,
+    /// SIGUSR1: вывести состояние демона в лог
+    DumpStatus,
+    /// SIGUSR2: переключить трассировку мониторинга и watchdog, см. [`trace_enabled`]
+    ToggleTrace,
+}
+
+/// Подписка на сигналы процесса
+#[cfg(unix)]
+pub struct Signals {
+    term: tokio::signal::unix::Signal,
+    int: tokio::signal::unix::Signal,
+    hup: tokio::signal::unix::Signal,
+    usr1: tokio::signal::unix::Signal,
+    usr2: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    pub fn new() -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        Ok(Self {
+            term: signal(SignalKind::terminate())?,
+            int: signal(SignalKind::interrupt())?,
+            hup: signal(SignalKind::hangup())?,
+            usr1: signal(SignalKind::user_defined1())?,
+            usr2: signal(SignalKind::user_defined2())?,
+        })
+    }
+
+    /// Ожидание следующего сигнала
+    pub async fn recv(&mut self) -> SignalAction {
+        tokio::select! {
+            _ = self.term.recv() => SignalAction::Shutdown("SIGTERM"),
+            _ = self.int.recv() => SignalAction::Shutdown("SIGINT"),
+            _ = self.hup.recv() => SignalAction::Reload,
+            _ = self.usr1.recv() => SignalAction::DumpStatus,
+            _ = self.usr2.recv() => SignalAction::ToggleTrace,
+        }
+    }
+}
+
+/// Подписка на сигналы процесса (на не-Unix системах доступен только Ctrl+C)
+#[cfg(not(unix))]
+pub struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    pub fn new() -> Result<Self> {
+        Ok(Self)
+    }
+
+    /// Ожидание следующего сигнала
+    pub async fn recv(&mut self) -> SignalAction {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+        SignalAction::Shutdown("Ctrl+C")
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Signature, VerifyingKey, SIGNATURE_LENGTH};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Расширение файла отделенной подписи: `foo_plugin.so` → `foo_plugin.so.sig`
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Политика доверия: библиотека принимается, только если ее подписал один из ключей
+///
+/// Подпись — ed25519 над содержимым файла, лежит рядом с библиотекой
+/// в виде 64 байт или hex-строки.
+#[derive(Debug, Clone)]
+pub struct TrustPolicy {
+    keys: Vec<VerifyingKey>,
+}
+
+impl TrustPolicy {
+    /// Публичные ключи в hex (32 байта)
+    pub fn from_hex_keys(keys: &[String]) -> Result<Self> {
+        if keys.is_empty() {
+            bail!("Trust policy requires at least one public key");
+        }
+
+        let keys = keys
+            .iter()
+            .map(|key| {
+                let bytes: [u8; 32] = hex::decode(key.trim())
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .with_context(|| format!("Public key {:?} is not 32 bytes of hex", key))?;
+                VerifyingKey::from_bytes(&bytes).with_context(|| format!("Invalid ed25519 public key {:?}", key))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { keys })
+    }
+
+    /// Проверка отделенной подписи библиотеки
+    pub fn verify(&self, path: &Path) -> Result<(), String> {
+        let signature_path = signature_path(path).ok_or_else(|| format!("no signature file for {:?}", path))?;
+        let signature = read_signature(&signature_path)?;
+        let content = std::fs::read(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+
+        match self.keys.iter().position(|key| key.verify_strict(&content, &signature).is_ok()) {
+            Some(index) => {
+                info!("Plugin {:?} signature verified with trusted key #{}", path, index);
+                Ok(())
+            }
+            None => Err(format!("signature {:?} does not match any trusted key", signature_path)),
+        }
+    }
+}
+
+/// Файл подписи рядом с библиотекой
+///
+/// Для символической ссылки (активная версия из хранилища) подпись ищется
+/// и рядом с ссылкой, и рядом с файлом, на который она указывает.
+pub fn signature_path(path: &Path) -> Option<PathBuf> {
+    let beside = |path: &Path| {
+        let mut name = path.file_name()?.to_os_string();
+        name.push(".");
+        name.push(SIGNATURE_EXTENSION);
+        Some(path.with_file_name(name)).filter(|sig| sig.is_file())
+    };
+
+    beside(path).or_else(|| beside(&path.canonicalize().ok()?))
+}
+
+fn read_signature(path: &Path) -> Result<Signature, String> {
+    let content = std::fs::read(path).map_err(|e| format!("failed to read signature {:?}: {}", path, e))?;
+
+    let bytes = match content.len() {
+        SIGNATURE_LENGTH => content,
+        _ => std::str::from_utf8(&content)
+            .ok()
+            .and_then(|text| hex::decode(text.trim()).ok())
+            .ok_or_else(|| format!("signature {:?} is neither raw bytes nor hex", path))?,
+    };
+
+    Signature::from_slice(&bytes).map_err(|_| format!("signature {:?} is not {} bytes long", path, SIGNATURE_LENGTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{library, TempDir};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn policy() -> TrustPolicy {
+        TrustPolicy::from_hex_keys(&[hex::encode(signing_key().verifying_key().to_bytes())]).unwrap()
+    }
+
+    /// Библиотека с подписью рядом: в виде 64 байт или hex-строки
+    fn signed(dir: &TempDir, name: &str, content: &str, as_hex: bool) -> PathBuf {
+        let path = dir.write(&library(name), content);
+        let signature = signing_key().sign(content.as_bytes()).to_bytes();
+        let sig_path = signature_path_for(&path);
+        if as_hex {
+            std::fs::write(sig_path, hex::encode(signature) + "\n").unwrap();
+        } else {
+            std::fs::write(sig_path, signature).unwrap();
+        }
+        path
+    }
+
+    fn signature_path_for(path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.{}", path.display(), SIGNATURE_EXTENSION))
+    }
+
+    #[test]
+    fn accepts_raw_and_hex_signatures() {
+        let dir = TempDir::new("signature-valid");
+        assert_eq!(policy().verify(&signed(&dir, "raw_plugin", "raw", false)), Ok(()));
+        assert_eq!(policy().verify(&signed(&dir, "hex_plugin", "hex", true)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_tampered_unsigned_and_foreign_libraries() {
+        let dir = TempDir::new("signature-invalid");
+
+        let tampered = signed(&dir, "tampered_plugin", "original", false);
+        std::fs::write(&tampered, "tampered").unwrap();
+        assert!(policy().verify(&tampered).unwrap_err().contains("does not match"));
+
+        let unsigned = dir.write(&library("unsigned_plugin"), "unsigned");
+        assert!(policy().verify(&unsigned).unwrap_err().contains("no signature"));
+
+        let other_key = SigningKey::from_bytes(&[9; 32]).verifying_key();
+        let other = TrustPolicy::from_hex_keys(&[hex::encode(other_key.to_bytes())]).unwrap();
+        assert!(other.verify(&signed(&dir, "foo_plugin", "foo", false)).is_err());
+
+        let garbage = dir.write(&library("garbage_plugin"), "garbage");
+        std::fs::write(signature_path_for(&garbage), "not a signature").unwrap();
+        assert!(policy().verify(&garbage).unwrap_err().contains("neither raw bytes nor hex"));
+    }
+
+    #[test]
+    fn finds_signature_next_to_symlink_target() {
+        let dir = TempDir::new("signature-symlink");
+        let target = signed(&dir, "releases/foo/1/foo_plugin", "foo", false);
+        let link = dir.path().join(library("foo_plugin"));
+        crate::store::symlink(&target, &link).unwrap();
+
+        assert_eq!(signature_path(&link), Some(signature_path_for(&target.canonicalize().unwrap())));
+        assert_eq!(policy().verify(&link), Ok(()));
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Как часто обновлять STATUS=, чтобы время работы в `systemctl status` не устаревало
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+struct State {
+    phase: String,
+    /// Описания загруженных плагинов и сервисов (`имя версия` из манифеста или имя файла)
+    plugins: Option<Vec<String>>,
+}
+
+/// Публикация состояния демона в `STATUS=` для `systemctl status`
+#[derive(Clone)]
+pub struct StatusReporter {
+    started_at: Instant,
+    state: Arc<Mutex<State>>,
+}
+
+impl StatusReporter {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            state: Arc::new(Mutex::new(State {
+                phase: "Starting".to_string(),
+                plugins: None,
+            })),
+        }
+    }
+
+    /// Сменить фазу жизненного цикла и опубликовать статус
+    pub fn phase(&self, phase: impl Into<String>) {
+        let phase = phase.into();
+        info!("Status: {}", phase);
+        self.state.lock().unwrap().phase = phase;
+        self.publish();
+    }
+
+    /// Обновить список загруженных плагинов и сервисов
+    pub fn set_plugins(&self, plugins: Vec<String>) {
+        self.state.lock().unwrap().plugins = Some(plugins);
+        self.publish();
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Текущая строка статуса
+    pub fn line(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut line = format!("{} | up {}", state.phase, format_uptime(self.uptime()));
+        match state.plugins.as_deref() {
+            Some([]) => line.push_str(" | 0 plugins"),
+            Some(plugins) => line.push_str(&format!(" | {} plugins: {}", plugins.len(), plugins.join(", "))),
+            None => {}
+        }
+        line
+    }
+
+    /// Периодическое обновление статуса в фоне
+    pub fn spawn_refresh(&self) -> tokio::task::JoinHandle<()> {
+        let reporter = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+                reporter.publish();
+            }
+        })
+    }
+
+    fn publish(&self) {
+        #[cfg(target_os = "linux")]
+        {
+            let status = libsystemd::daemon::NotifyState::Status(self.line());
+            if let Err(e) = libsystemd::daemon::notify(false, &[status]) {
+                tracing::warn!("Failed to send systemd STATUS notification: {}", e);
+            }
+        }
+    }
+}
+
+impl Default for StatusReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, minutes) = (secs / 86_400, secs % 86_400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m {}s", minutes, secs % 60)
+    }
+}
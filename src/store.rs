@@ -0,0 +1,358 @@
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::checksum::sha256_file;
+use crate::config::Config;
+use crate::dependencies::DependencyGraph;
+use crate::library::LibraryNaming;
+use crate::manifest::{manifest_path, Manifest};
+use crate::signature::{signature_path, TrustPolicy, SIGNATURE_EXTENSION};
+
+/// Операции с хранилищем версий плагинов
+#[derive(Debug, Clone, Subcommand)]
+pub enum PluginCommand {
+    /// Установить библиотеку в хранилище и активировать ее
+    Install {
+        /// Файл библиотеки плагина или сервиса
+        file: PathBuf,
+        /// Версия, по умолчанию — из манифеста или начало SHA-256 файла
+        #[arg(long)]
+        version: Option<String>,
+        /// Только установить, не активируя
+        #[arg(long)]
+        no_activate: bool,
+    },
+    /// Активировать установленную версию
+    Activate { name: String, version: String },
+    /// Вернуть версию, установленную перед активной
+    Rollback { name: String },
+    /// Показать установленные версии
+    List,
+}
+
+/// Плагин в хранилище
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredPlugin {
+    pub name: String,
+    /// Версии в порядке установки
+    pub versions: Vec<String>,
+    pub active: Option<String>,
+}
+
+/// Хранилище версий плагинов в директории плагинов
+///
+/// ```text
+/// plugins/releases/<name>/<version>/<lib>          библиотека версии
+/// plugins/releases/<name>/<version>/<lib>.sha256   ее контрольная сумма
+/// plugins/releases/<name>/<version>/<lib>.sig      ее подпись, если есть
+/// plugins/releases/<name>/<version>/<name>.toml    ее манифест, если есть
+/// plugins/releases/<name>/versions                 версии в порядке установки
+/// plugins/<lib> -> releases/<name>/<version>/<lib> активная версия
+/// ```
+///
+/// Установка идет через временную директорию, а активация — атомарной заменой
+/// символической ссылки `plugins/<lib>`, поэтому мониторинг плагинов видит только
+/// момент активации, а не запись файлов в `releases/`.
+pub struct PluginStore {
+    root: PathBuf,
+    /// Все директории плагинов, в которых ищутся сервисы для проверки зависимостей
+    plugin_dirs: Vec<PathBuf>,
+    /// Какие файлы считаются библиотеками плагинов и сервисов
+    naming: LibraryNaming,
+    keep_versions: usize,
+    trust: Option<TrustPolicy>,
+}
+
+impl PluginStore {
+    /// Хранилище в первой директории плагинов
+    pub fn new(config: &Config) -> Result<Self> {
+        let root = config.plugin_dirs.first().context("No plugins directory configured")?;
+        Ok(Self {
+            root: root.clone(),
+            plugin_dirs: config.plugin_dirs.clone(),
+            naming: config.library_naming.clone(),
+            keep_versions: config.store_keep_versions.max(1),
+            trust: config.trust_policy.clone(),
+        })
+    }
+
+    /// Хранилище в `root` без проверки подписей
+    #[cfg(test)]
+    pub fn in_dir(root: &Path, naming: LibraryNaming) -> Self {
+        Self { root: root.to_path_buf(), plugin_dirs: vec![root.to_path_buf()], naming, keep_versions: 5, trust: None }
+    }
+
+    /// Скопировать библиотеку в хранилище и вернуть (имя, версия)
+    pub fn install(&self, file: &Path, version: Option<&str>) -> Result<(String, String)> {
+        let lib = file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .filter(|_| self.naming.is_library(file))
+            .with_context(|| format!("{:?} is not a plugin or service library", file))?;
+        let name = plugin_name(lib).to_string();
+
+        let checksum = sha256_file(file).with_context(|| format!("Failed to read {:?}", file))?;
+        let manifest = Manifest::load(file).map_err(anyhow::Error::msg)?;
+        let version = match (version, &manifest) {
+            (Some(version), _) => version.to_string(),
+            (None, Some(manifest)) => manifest.version.to_string(),
+            (None, None) => checksum[..12].to_string(),
+        };
+        validate_component(&version)?;
+
+        let plugin_dir = self.releases().join(&name);
+        let version_dir = plugin_dir.join(&version);
+        if version_dir.exists() {
+            bail!("{} version {} is already installed", name, version);
+        }
+
+        let staging = plugin_dir.join(format!(".staging-{}", version));
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        std::fs::create_dir_all(&staging)?;
+        std::fs::copy(file, staging.join(lib)).with_context(|| format!("Failed to copy {:?}", file))?;
+
+        // Скопированный файл должен совпасть с исходным
+        if sha256_file(&staging.join(lib))? != checksum {
+            std::fs::remove_dir_all(&staging)?;
+            bail!("Checksum mismatch while staging {:?}", file);
+        }
+        std::fs::write(staging.join(format!("{}.sha256", lib)), &checksum)?;
+        if let Some(signature) = signature_path(file) {
+            std::fs::copy(&signature, staging.join(format!("{}.{}", lib, SIGNATURE_EXTENSION)))?;
+        }
+        if let Some(manifest) = manifest_path(file) {
+            std::fs::copy(&manifest, staging.join(Path::new(lib).with_extension("toml")))?;
+        }
+        std::fs::rename(&staging, &version_dir)?;
+
+        let mut versions = self.versions(&name);
+        versions.push(version.clone());
+        std::fs::write(plugin_dir.join("versions"), versions.join("\n") + "\n")?;
+
+        info!("Installed {} version {} (sha256 {})", name, version, checksum);
+        Ok((name, version))
+    }
+
+    /// Атомарно переключить активную версию плагина
+    pub fn activate(&self, name: &str, version: &str) -> Result<()> {
+        validate_component(name)?;
+        validate_component(version)?;
+
+        let version_dir = self.releases().join(name).join(version);
+        let lib = self.library_in(&version_dir)?;
+
+        let expected = std::fs::read_to_string(version_dir.join(format!("{}.sha256", lib)))
+            .with_context(|| format!("Missing checksum for {} version {}", name, version))?;
+        let actual = sha256_file(&version_dir.join(&lib))?;
+        if expected.trim() != actual {
+            bail!("Checksum mismatch for {} version {}: expected {}, got {}", name, version, expected.trim(), actual);
+        }
+        if let Some(trust) = &self.trust {
+            trust
+                .verify(&version_dir.join(&lib))
+                .map_err(|reason| anyhow::anyhow!("{} version {} rejected by trust policy: {}", name, version, reason))?;
+        }
+
+        // Новая версия не должна оставить ее или зависящие от нее плагины без нужных сервисов
+        let link = self.root.join(&lib);
+        let current = DependencyGraph::scan(&self.plugin_dirs, &self.naming);
+        let candidate = current.clone().with_library(&link, &version_dir.join(&lib));
+        if let Err(broken) = candidate.check_change(&current, std::slice::from_ref(&link)) {
+            let reasons: Vec<String> = broken.into_iter().map(|(_, reason)| reason).collect();
+            bail!("Refusing to activate {} version {}: {}", name, version, reasons.join("; "));
+        }
+
+        let tmp_link = self.root.join(format!(".{}.activating", lib));
+        let target = Path::new("releases").join(name).join(version).join(&lib);
+
+        let _ = std::fs::remove_file(&tmp_link);
+        symlink(&target, &tmp_link)?;
+        std::fs::rename(&tmp_link, &link)
+            .with_context(|| format!("Failed to activate {} version {}", name, version))?;
+
+        info!("Activated {} version {}", name, version);
+        self.prune(name)?;
+        Ok(())
+    }
+
+    /// Активировать версию, установленную перед текущей
+    pub fn rollback(&self, name: &str) -> Result<String> {
+        let versions = self.versions(name);
+        let active = self.active_version(name).with_context(|| format!("{} has no active version", name))?;
+        let position = versions
+            .iter()
+            .position(|v| *v == active)
+            .with_context(|| format!("Active version {} of {} is not in the store", active, name))?;
+        let Some(previous) = position.checked_sub(1).map(|i| versions[i].clone()) else {
+            bail!("{} has no version before {}", name, active);
+        };
+
+        self.activate(name, &previous)?;
+        Ok(previous)
+    }
+
+    /// Плагин и версия, которые активирует символическая ссылка `library`,
+    /// `None` — библиотека не из хранилища
+    pub fn active_release(&self, library: &Path) -> Option<(String, String)> {
+        if library.parent() != Some(self.root.as_path()) {
+            return None;
+        }
+        release_of(&std::fs::read_link(library).ok()?)
+    }
+
+    /// Установленные плагины
+    pub fn list(&self) -> Result<Vec<StoredPlugin>> {
+        let mut plugins = Vec::new();
+        let Ok(entries) = std::fs::read_dir(self.releases()) else {
+            return Ok(plugins);
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            plugins.push(StoredPlugin {
+                active: self.active_version(&name),
+                versions: self.versions(&name),
+                name,
+            });
+        }
+
+        plugins.sort();
+        Ok(plugins)
+    }
+
+    /// Активная версия по символической ссылке `plugins/<lib>`
+    fn active_version(&self, name: &str) -> Option<String> {
+        let version = self.versions(name).pop()?;
+        let lib = self.library_in(&self.releases().join(name).join(version)).ok()?;
+        let (_, version) = release_of(&std::fs::read_link(self.root.join(lib)).ok()?)?;
+        Some(version)
+    }
+
+    /// Удалить старые версии, оставив `keep_versions` последних и активную
+    fn prune(&self, name: &str) -> Result<()> {
+        let mut versions = self.versions(name);
+        let active = self.active_version(name);
+        let excess = versions.len().saturating_sub(self.keep_versions);
+
+        let mut removed = Vec::new();
+        for version in versions.iter().take(excess) {
+            if Some(version) == active.as_ref() {
+                continue;
+            }
+            std::fs::remove_dir_all(self.releases().join(name).join(version))?;
+            info!("Pruned {} version {}", name, version);
+            removed.push(version.clone());
+        }
+
+        if !removed.is_empty() {
+            versions.retain(|v| !removed.contains(v));
+            std::fs::write(self.releases().join(name).join("versions"), versions.join("\n") + "\n")?;
+        }
+        Ok(())
+    }
+
+    fn versions(&self, name: &str) -> Vec<String> {
+        std::fs::read_to_string(self.releases().join(name).join("versions"))
+            .map(|content| content.lines().filter(|l| !l.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    fn releases(&self) -> PathBuf {
+        self.root.join("releases")
+    }
+
+    /// Библиотека внутри директории версии
+    fn library_in(&self, version_dir: &Path) -> Result<String> {
+        std::fs::read_dir(version_dir)
+            .with_context(|| format!("Version directory {:?} not found", version_dir))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| self.naming.is_library(path))
+            .and_then(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .with_context(|| format!("No plugin library in {:?}", version_dir))
+    }
+}
+
+/// Выполнить команду хранилища и завершиться
+pub async fn run_command(command: PluginCommand, config: &Config) -> Result<()> {
+    let store = PluginStore::new(config)?;
+
+    match command {
+        PluginCommand::Install { file, version, no_activate } => {
+            // dlopen ищет относительные имена по путям библиотек, а не в текущей директории
+            let file = std::fs::canonicalize(&file).with_context(|| format!("Plugin file {:?} not found", file))?;
+
+            // Неподписанная или непроходящая проверку библиотека не попадает в хранилище
+            if let Some(trust) = &config.trust_policy {
+                trust
+                    .verify(&file)
+                    .map_err(|reason| anyhow::anyhow!("{:?} rejected by trust policy: {}", file, reason))?;
+            }
+            if let Some(requirements) = &config.plugin_validation {
+                crate::validate::validate(&file, requirements)
+                    .await
+                    .map_err(|reason| anyhow::anyhow!("{:?} failed validation: {}", file, reason))?;
+            }
+
+            let (name, version) = store.install(&file, version.as_deref())?;
+            if !no_activate {
+                store.activate(&name, &version)?;
+            }
+        }
+        PluginCommand::Activate { name, version } => store.activate(&name, &version)?,
+        PluginCommand::Rollback { name } => {
+            let version = store.rollback(&name)?;
+            info!("Rolled back {} to version {}", name, version);
+        }
+        PluginCommand::List => {
+            for plugin in store.list()? {
+                let versions: Vec<String> = plugin
+                    .versions
+                    .into_iter()
+                    .map(|v| if Some(&v) == plugin.active.as_ref() { format!("{} (active)", v) } else { v })
+                    .collect();
+                println!("{}: {}", plugin.name, versions.join(", "));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Плагин и версия по цели символической ссылки активной версии: `releases/<name>/<version>/<lib>`
+pub fn release_of(target: &Path) -> Option<(String, String)> {
+    let components: Vec<String> = target.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    match components.as_slice() {
+        [releases, name, version, _] if releases == "releases" => Some((name.clone(), version.clone())),
+        _ => None,
+    }
+}
+
+/// Создать символическую ссылку на библиотеку
+pub fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(target, link);
+}
+
+/// Имя плагина — имя файла библиотеки без расширения
+fn plugin_name(lib: &str) -> &str {
+    lib.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(lib)
+}
+
+/// Имя и версия используются как компоненты пути и не должны его покидать
+fn validate_component(value: &str) -> Result<()> {
+    if value.is_empty() || value.starts_with('.') || value.contains(['/', '\\']) {
+        bail!("Invalid name or version {:?}", value);
+    }
+    Ok(())
+}
@@ -0,0 +1,382 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Stdio};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::crash_loop;
+use crate::dependencies::DependencyGraph;
+use crate::library::LibraryNaming;
+use crate::manifest::Manifest;
+use crate::quarantine;
+use crate::status::StatusReporter;
+use crate::store::PluginStore;
+use crate::watcher::ChangeSet;
+
+/// Скрытая команда, с которой демон перезапускает сам себя для проверки библиотеки
+const VALIDATE_COMMAND: &str = "__validate-plugin";
+
+/// Сколько ждем дочерний процесс проверки
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Требования к библиотеке плагина, которые проверяются до перезапуска
+#[derive(Debug, Clone, Default)]
+pub struct PluginRequirements {
+    /// Символы, которые библиотека обязана экспортировать
+    pub required_symbols: Vec<String>,
+    /// Символ со значением ABI (`u32`) и ожидаемое значение
+    pub abi: Option<(String, u32)>,
+}
+
+/// Пропускает дальше только изменения, все новые и измененные библиотеки которых прошли проверку
+///
+/// Сначала проверяется подпись (если задана политика доверия) и манифест, и только
+/// подписанная совместимая библиотека загружается для проверки символов. Отклоненные
+/// изменения логируются и попадают в `STATUS=`, бот продолжает работать со старыми плагинами.
+/// Библиотеки, не прошедшие проверку подписи, манифеста или загрузки, переносятся
+/// в карантин в `state_dir`, чтобы их не загрузил следующий перезапуск. Вместо
+/// измененных возвращается предыдущая версия, см. [`restore_previous`].
+///
+/// Изменение также отклоняется, если после него затронутым плагинам не хватает
+/// сервисов нужных версий. Тогда в карантин переносятся новые и измененные библиотеки
+/// этого изменения, а если в нем только удаления — плагины, оставшиеся без сервисов.
+/// Затронутые через сервисы плагины попадают в [`ChangeSet::affected`].
+pub fn gate(mut changes: mpsc::Receiver<ChangeSet>, config: &Config, status: StatusReporter) -> mpsc::Receiver<ChangeSet> {
+    let (validated_tx, validated_rx) = mpsc::channel(8);
+    let plugin_dirs = config.plugin_dirs.clone();
+    let naming = config.library_naming.clone();
+    let requirements = config.plugin_validation.clone();
+    let trust = config.trust_policy.clone();
+    let state_dir = config.state_dir.clone();
+    let store = PluginStore::new(config).ok();
+
+    tokio::spawn(async move {
+        // Граф зависимостей плагинов на момент последнего обработанного изменения
+        let mut graph = DependencyGraph::scan(&plugin_dirs, &naming);
+        // Библиотеки, перенесенные в карантин: их исчезновение — не изменение плагинов
+        let mut quarantined: BTreeSet<PathBuf> = BTreeSet::new();
+
+        loop {
+            let mut change_set = tokio::select! {
+                change_set = changes.recv() => match change_set {
+                    Some(change_set) => change_set,
+                    None => return,
+                },
+                // Получатель закрыт (например, мониторинг перезапущен после reload)
+                _ = validated_tx.closed() => return,
+            };
+
+            for path in change_set.added.iter().chain(&change_set.modified) {
+                quarantined.remove(path);
+            }
+            change_set.removed.retain(|path| !quarantined.remove(path));
+            if change_set.is_empty() {
+                continue;
+            }
+
+            let mut rejected = Vec::new();
+            // Библиотеки, которые нельзя оставлять в директории плагинов до следующего перезапуска
+            let mut isolate = Vec::new();
+            for path in change_set.added.iter().chain(&change_set.modified) {
+                if let Some(Err(reason)) = trust.as_ref().map(|trust| trust.verify(path)) {
+                    error!("Plugin {:?} rejected by trust policy: {}", path, reason);
+                    rejected.push(path.clone());
+                    isolate.push(path.clone());
+                    continue;
+                }
+                if let Err(reason) = check_manifest(path, requirements.as_ref()) {
+                    error!("Plugin {:?} failed manifest check: {}", path, reason);
+                    rejected.push(path.clone());
+                    isolate.push(path.clone());
+                    continue;
+                }
+                if let Some(requirements) = &requirements {
+                    if let Err(reason) = validate(path, requirements).await {
+                        error!("Plugin {:?} failed validation: {}", path, reason);
+                        rejected.push(path.clone());
+                        isolate.push(path.clone());
+                    }
+                }
+            }
+
+            let new_graph = DependencyGraph::scan(&plugin_dirs, &naming);
+            let changed: Vec<PathBuf> =
+                change_set.added.iter().chain(&change_set.modified).chain(&change_set.removed).cloned().collect();
+            let affected = match new_graph.check_change(&graph, &changed) {
+                Ok(affected) => affected,
+                Err(broken) => {
+                    let mut culprits: Vec<PathBuf> = change_set
+                        .added
+                        .iter()
+                        .chain(&change_set.modified)
+                        .filter(|path| !isolate.contains(path))
+                        .cloned()
+                        .collect();
+                    if change_set.added.is_empty() && change_set.modified.is_empty() {
+                        culprits = broken.iter().map(|(plugin, _)| plugin.clone()).collect();
+                    }
+                    isolate.extend(culprits);
+
+                    for (plugin, reason) in broken {
+                        error!("Plugin {:?} has unsatisfied dependencies: {}", plugin, reason);
+                        rejected.push(plugin);
+                    }
+                    Default::default()
+                }
+            };
+            let previous = std::mem::replace(&mut graph, new_graph);
+
+            if !isolate.is_empty() {
+                let plugins = Plugins { dirs: &plugin_dirs, naming: &naming, store: store.as_ref(), state_dir: &state_dir };
+                graph = isolate_plugins(isolate, &change_set.modified, previous, &plugins, &mut quarantined);
+            }
+
+            if rejected.is_empty() {
+                change_set.affected = affected.into_iter().filter(|plugin| !changed.contains(plugin)).collect();
+                if validated_tx.send(change_set).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+
+            warn!("Plugin change rejected, keeping current plugins running: {}", change_set);
+            status.phase(format!("Running, rejected plugin change (failed checks: {:?})", rejected));
+        }
+    });
+
+    validated_rx
+}
+
+/// Где лежат плагины, которые переносятся в карантин или возвращаются к предыдущей версии
+struct Plugins<'a> {
+    dirs: &'a [PathBuf],
+    naming: &'a LibraryNaming,
+    store: Option<&'a PluginStore>,
+    state_dir: &'a Path,
+}
+
+/// Перенос библиотек в карантин вместе с плагинами, которые остались без их сервисов
+///
+/// Библиотеки из `modified` не переносятся, а возвращаются к предыдущей версии.
+/// `graph` — граф до отклоненного изменения, в котором зависимости плагинов выполнялись.
+/// Возвращает граф по содержимому директорий после переноса.
+fn isolate_plugins(
+    mut isolate: Vec<PathBuf>,
+    modified: &[PathBuf],
+    mut graph: DependencyGraph,
+    plugins: &Plugins,
+    quarantined: &mut BTreeSet<PathBuf>,
+) -> DependencyGraph {
+    while !isolate.is_empty() {
+        let mut moved = Vec::new();
+        let mut restored = Vec::new();
+        for path in isolate {
+            if modified.contains(&path) {
+                match restore_previous(&path, plugins) {
+                    Ok(()) => restored.push(path),
+                    Err(e) => error!("Not quarantining plugin {:?}, it will be loaded on next restart: {:#}", path, e),
+                }
+                continue;
+            }
+            match quarantine::isolate(&path, plugins.dirs, plugins.state_dir) {
+                Ok(_) => moved.push(path),
+                Err(e) => error!("Failed to quarantine plugin {:?}, it may be loaded on next restart: {:?}", path, e),
+            }
+        }
+
+        let new_graph = DependencyGraph::scan(plugins.dirs, plugins.naming);
+        let changed: Vec<PathBuf> = moved.iter().chain(&restored).cloned().collect();
+        isolate = match new_graph.check_change(&graph, &changed) {
+            Ok(_) => Vec::new(),
+            Err(broken) => broken
+                .into_iter()
+                .map(|(plugin, reason)| {
+                    error!("Plugin {:?} lost its dependencies to quarantine: {}", plugin, reason);
+                    plugin
+                })
+                .collect(),
+        };
+        quarantined.extend(moved);
+        graph = new_graph;
+    }
+    graph
+}
+
+/// Вернуть предыдущую версию измененной библиотеки, не прошедшей проверку
+///
+/// Активная версия из хранилища переключается на установленную перед ней, остальные
+/// библиотеки переносятся в карантин и восстанавливаются из копии последнего рабочего
+/// набора (см. [`crash_loop::last_good_dir`]). Без предыдущей версии библиотека остается
+/// на месте: после переноса в карантин плагин пропал бы при следующем перезапуске.
+fn restore_previous(path: &Path, plugins: &Plugins) -> anyhow::Result<()> {
+    if let Some((store, (name, _))) = plugins.store.and_then(|store| Some((store, store.active_release(path)?))) {
+        let version = store.rollback(&name)?;
+        warn!("Rolled back rejected plugin {:?} to version {}", path, version);
+        return Ok(());
+    }
+
+    let last_good_dir = crash_loop::last_good_dir(plugins.state_dir);
+    let backup = quarantine::backup_of(path, plugins.dirs, &last_good_dir)
+        .ok_or_else(|| anyhow::anyhow!("no previous version to restore"))?;
+    quarantine::isolate(path, plugins.dirs, plugins.state_dir)?;
+    quarantine::restore(path, plugins.dirs, &last_good_dir)?;
+    warn!("Restored previous version of plugin {:?} from {:?}", path, backup);
+    Ok(())
+}
+
+/// Проверка манифеста библиотеки, если он есть, и совместимости с ABI anicore
+fn check_manifest(path: &Path, requirements: Option<&PluginRequirements>) -> Result<(), String> {
+    let Some(manifest) = Manifest::load(path)? else {
+        return Ok(());
+    };
+
+    if let Some((_, abi_version)) = requirements.and_then(|requirements| requirements.abi.as_ref()) {
+        manifest.check_abi(*abi_version)?;
+    }
+    info!("Plugin {:?} manifest: {}", path, manifest);
+    Ok(())
+}
+
+/// Проверка библиотеки плагина в отдельном короткоживущем процессе
+///
+/// Библиотека загружается через `dlopen` в дочернем процессе, поэтому падение
+/// в ее конструкторах не затрагивает работающий бот.
+pub async fn validate(path: &Path, requirements: &PluginRequirements) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("failed to locate own executable: {}", e))?;
+
+    let mut command = tokio::process::Command::new(exe);
+    command.arg(VALIDATE_COMMAND).arg(path);
+    for symbol in &requirements.required_symbols {
+        command.arg("--symbol").arg(symbol);
+    }
+    if let Some((symbol, version)) = &requirements.abi {
+        command.arg("--abi").arg(format!("{}={}", symbol, version));
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let child = command.spawn().map_err(|e| format!("failed to spawn validator: {}", e))?;
+    let output = match tokio::time::timeout(VALIDATION_TIMEOUT, child.wait_with_output()).await {
+        Ok(output) => output.map_err(|e| format!("validator failed: {}", e))?,
+        Err(_) => return Err(format!("validator timed out after {:?}", VALIDATION_TIMEOUT)),
+    };
+
+    if output.status.success() {
+        info!("Plugin {:?} passed validation", path);
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(match stderr.trim() {
+            "" => format!("validator exited with {}", output.status),
+            message => message.to_string(),
+        })
+    }
+}
+
+/// Выполнить проверку, если процесс запущен как валидатор
+///
+/// Возвращает `None` при обычном запуске демона.
+pub fn run_if_requested() -> Option<ExitCode> {
+    let mut args = std::env::args_os().skip(1);
+    if args.next()? != VALIDATE_COMMAND {
+        return None;
+    }
+
+    let Some(path) = args.next().map(PathBuf::from) else {
+        eprintln!("missing plugin path");
+        return Some(ExitCode::FAILURE);
+    };
+
+    let mut requirements = PluginRequirements::default();
+    while let Some(flag) = args.next() {
+        let value = args.next().and_then(|v| v.into_string().ok());
+        match (flag.to_str(), value) {
+            (Some("--symbol"), Some(symbol)) => requirements.required_symbols.push(symbol),
+            (Some("--abi"), Some(abi)) => {
+                let parsed = abi.split_once('=').and_then(|(symbol, version)| {
+                    version.parse().ok().map(|version| (symbol.to_string(), version))
+                });
+                match parsed {
+                    Some(abi) => requirements.abi = Some(abi),
+                    None => {
+                        eprintln!("invalid --abi value {:?}", abi);
+                        return Some(ExitCode::FAILURE);
+                    }
+                }
+            }
+            _ => {
+                eprintln!("invalid validator argument {:?}", flag);
+                return Some(ExitCode::FAILURE);
+            }
+        }
+    }
+
+    Some(match check_library(&path, &requirements) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    })
+}
+
+/// Загрузка библиотеки и проверка экспортируемых символов (выполняется в дочернем процессе)
+fn check_library(path: &Path, requirements: &PluginRequirements) -> Result<(), String> {
+    // SAFETY: библиотека загружается в отдельном процессе, который сразу завершится
+    let library = unsafe { libloading::Library::new(path) }
+        .map_err(|e| format!("failed to load {:?}: {}", path, e))?;
+
+    for symbol in &requirements.required_symbols {
+        // SAFETY: символ только ищется, но не вызывается
+        unsafe { library.get::<*const ()>(symbol.as_bytes()) }
+            .map_err(|_| format!("missing required symbol `{}`", symbol))?;
+    }
+
+    if let Some((symbol, expected)) = &requirements.abi {
+        // SAFETY: по соглашению символ ABI указывает на статическую `u32`
+        let version = unsafe {
+            let pointer = library
+                .get::<*const u32>(symbol.as_bytes())
+                .map_err(|_| format!("missing ABI symbol `{}`", symbol))?;
+            **pointer
+        };
+        if version != *expected {
+            return Err(format!("ABI version mismatch: plugin has {}, anicore expects {}", version, expected));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{library, TempDir};
+
+    #[test]
+    fn rejected_update_is_replaced_with_last_good_copy() {
+        let dir = TempDir::new("restore-previous");
+        let plugin_dirs = vec![dir.path().join("plugins")];
+        let state_dir = dir.path().join("state");
+        let naming = LibraryNaming::default();
+        let plugins = Plugins { dirs: &plugin_dirs, naming: &naming, store: None, state_dir: &state_dir };
+
+        let updated = dir.write(&format!("plugins/{}", library("foo_plugin")), "broken");
+        let manifest = dir.write("plugins/foo_plugin.toml", "broken");
+        dir.write(&format!("state/last-good/0/{}", library("foo_plugin")), "good");
+        restore_previous(&updated, &plugins).unwrap();
+        assert_eq!(std::fs::read_to_string(&updated).unwrap(), "good");
+        // Манифест новой версии уходит в карантин вместе с библиотекой
+        assert!(!manifest.exists());
+
+        // Без предыдущей версии плагин остается на месте
+        let added = dir.write(&format!("plugins/{}", library("bar_plugin")), "broken");
+        assert!(restore_previous(&added, &plugins).is_err());
+        assert_eq!(std::fs::read_to_string(&added).unwrap(), "broken");
+    }
+}
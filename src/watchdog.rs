@@ -0,0 +1,165 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Порог зависания по умолчанию, если watchdog systemd не настроен
+const DEFAULT_STALL_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// Результат проверки живости бота
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    /// Бот работает, можно отправлять WATCHDOG=1
+    Healthy,
+    /// Бот не подавал признаков жизни дольше порога
+    Stalled(Duration),
+}
+
+/// Проверка живости, которую watchdog выполняет перед каждым пингом
+pub trait HealthCheck: Send + Sync + 'static {
+    fn check(&self) -> Health;
+}
+
+/// Heartbeat, по которому watchdog судит, что бот не завис
+///
+/// Сейчас его обновляет [`with_heartbeat`] при опросе future бота, см. ограничения там.
+#[derive(Clone)]
+pub struct Heartbeat {
+    origin: Instant,
+    last_beat_ms: Arc<AtomicU64>,
+    stall_threshold: Duration,
+}
+
+impl Heartbeat {
+    pub fn new(stall_threshold: Duration) -> Self {
+        Self {
+            origin: Instant::now(),
+            last_beat_ms: Arc::new(AtomicU64::new(0)),
+            stall_threshold,
+        }
+    }
+
+    /// Отметить, что бот жив
+    pub fn bump(&self) {
+        let now_ms = self.origin.elapsed().as_millis() as u64;
+        self.last_beat_ms.store(now_ms, Ordering::Relaxed);
+    }
+
+    /// Время с последнего обновления heartbeat
+    pub fn since_last_beat(&self) -> Duration {
+        let last = Duration::from_millis(self.last_beat_ms.load(Ordering::Relaxed));
+        self.origin.elapsed().saturating_sub(last)
+    }
+
+    pub fn stall_threshold(&self) -> Duration {
+        self.stall_threshold
+    }
+}
+
+impl HealthCheck for Heartbeat {
+    fn check(&self) -> Health {
+        let silence = self.since_last_beat();
+        if silence > self.stall_threshold {
+            Health::Stalled(silence)
+        } else {
+            Health::Healthy
+        }
+    }
+}
+
+/// Выполняет future бота, обновляя heartbeat, пока тот продолжает опрашиваться
+///
+/// Heartbeat обновляется по таймеру в той же задаче, что и сам бот, а не из цикла бота:
+/// anicore::Bot не дает ни хука в свой цикл, ни проверки состояния. Поэтому обновления
+/// прекращаются, только если бот заблокировал поток исполнителя или задача перестала
+/// опрашиваться. Бот, который потерял соединение или ждет вечно, не блокируя поток
+/// (например, зависший `await`), по-прежнему считается живым — это проверка того, что
+/// исполнитель не заблокирован, а не живости бота. Чтобы отличать такие зависания,
+/// `bump` должен вызывать сам цикл бота, для чего нужна поддержка в anicore.
+pub async fn with_heartbeat<F: Future>(fut: F, heartbeat: Heartbeat) -> F::Output {
+    let tick = (heartbeat.stall_threshold() / 4).max(Duration::from_millis(100));
+    let mut ticker = tokio::time::interval(tick);
+    tokio::pin!(fut);
+
+    loop {
+        tokio::select! {
+            biased;
+            output = &mut fut => return output,
+            _ = ticker.tick() => heartbeat.bump(),
+        }
+    }
+}
+
+/// Порог зависания бота: из конфигурации, иначе половина таймаута watchdog systemd
+///
+/// Пинг уходит раз в половину таймаута, поэтому зависание с порогом не меньше таймаута
+/// обнаружилось бы уже после того, как systemd сам сочтет сервис зависшим и убьет его
+/// без `WATCHDOG=trigger`. Такой порог из конфигурации заменяется половиной таймаута.
+pub fn stall_threshold(configured: Option<Duration>) -> Duration {
+    #[cfg(target_os = "linux")]
+    if let Some(timeout) = libsystemd::daemon::watchdog_enabled(false) {
+        return match configured {
+            Some(threshold) if threshold >= timeout => {
+                warn!(
+                    "Watchdog stall threshold {:?} is not below systemd watchdog timeout {:?}, using {:?}",
+                    threshold,
+                    timeout,
+                    timeout / 2
+                );
+                timeout / 2
+            }
+            Some(threshold) => threshold,
+            None => timeout / 2,
+        };
+    }
+
+    configured.unwrap_or(DEFAULT_STALL_THRESHOLD)
+}
+
+/// Запуск периодических WATCHDOG-пингов systemd
+///
+/// Интервал берется из `WATCHDOG_USEC` (с учетом `WATCHDOG_PID`), пинг отправляется
+/// на половине таймаута и только если `health` сообщает, что бот жив. При зависании
+/// пинги прекращаются и отправляется `WATCHDOG=trigger`, чтобы systemd перезапустил сервис.
+/// Если watchdog для сервиса не настроен, задача не запускается.
+#[cfg(target_os = "linux")]
+pub fn start<H: HealthCheck>(health: H) -> Option<tokio::task::JoinHandle<()>> {
+    use libsystemd::daemon::{notify, NotifyState};
+
+    let Some(timeout) = libsystemd::daemon::watchdog_enabled(false) else {
+        info!("systemd watchdog is not enabled, watchdog pings disabled");
+        return None;
+    };
+
+    let interval = timeout / 2;
+    info!("systemd watchdog enabled: timeout {:?}, ping interval {:?}", timeout, interval);
+
+    Some(tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            match health.check() {
+                Health::Healthy => {
+                    if crate::signals::trace_enabled() {
+                        info!("Sending systemd WATCHDOG notification");
+                    }
+                    if let Err(e) = notify(false, &[NotifyState::Watchdog]) {
+                        warn!("Failed to send systemd WATCHDOG notification: {}", e);
+                    }
+                }
+                Health::Stalled(silence) => {
+                    warn!("Bot stalled for {:?}, sending WATCHDOG=trigger and withholding pings", silence);
+                    if let Err(e) = notify(false, &[NotifyState::Other("WATCHDOG=trigger".to_string())]) {
+                        warn!("Failed to send systemd WATCHDOG=trigger notification: {}", e);
+                    }
+                    return;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn start<H: HealthCheck>(_health: H) -> Option<tokio::task::JoinHandle<()>> {
+    None
+}
@@ -0,0 +1,706 @@
+use anyhow::Result;
+use globset::GlobSet;
+use notify::event::{AccessKind, AccessMode, CreateKind, ModifyKind};
+use notify::{Event as NotifyEvent, EventKind, PollWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::inventory::{content_hash, Inventory};
+use crate::library::{LibraryMatch, LibraryNaming};
+
+/// Размер, время изменения содержимого и время изменения метаданных (ctime) файла,
+/// `None` — файла нет
+///
+/// Для плагина в своей поддиректории — суммарный размер и последние времена изменения
+/// всех файлов и директорий внутри нее. Время изменения метаданных отличает chmod и
+/// chown, которые не меняют ни размер, ни время изменения содержимого.
+type FileState = Option<(u64, SystemTime, SystemTime)>;
+
+/// Поддиректория директории плагинов, в которой лежит хранилище версий
+const STORE_DIR: &str = "releases";
+
+/// Способ получения изменений в директориях плагинов
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchBackend {
+    /// События ОС (inotify), с переходом на опрос, если они недоступны или ненадежны
+    #[default]
+    #[serde(alias = "inotify")]
+    #[value(alias = "inotify")]
+    Native,
+    /// Только периодический опрос
+    Poll,
+    /// События ОС и опрос одновременно
+    Both,
+}
+
+/// Настройки мониторинга директорий плагинов
+#[derive(Debug, Clone)]
+pub struct WatchSettings {
+    /// Тихий период, после которого накопленные изменения обрабатываются
+    pub quiet_period: Duration,
+    pub backend: WatchBackend,
+    /// Интервал опроса для [`WatchBackend::Poll`] и перехода на опрос
+    pub poll_interval: Duration,
+    /// Шаблоны имен файлов и директорий, изменения которых игнорируются
+    pub ignore: GlobSet,
+    /// Считать изменением плагина и изменение только его метаданных (touch, chmod)
+    ///
+    /// По умолчанию плагин изменился, только если хеш его содержимого отличается от того,
+    /// с которым работает бот. Опрос замечает только изменение размера и времени изменения
+    /// содержимого, поэтому chmod приводит к перезапуску лишь при событиях ОС.
+    pub restart_on_touch: bool,
+}
+
+/// Сколько тихих периодов без изменений ждать close-write, прежде чем считать запись законченной
+///
+/// Нужен для файлов, которые держат открытыми без записи или создают без открытия (ссылки).
+const STALLED_WRITE_PERIODS: u32 = 10;
+
+/// Что событие говорит о записи файла
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Write {
+    /// Файл открыт на запись, об окончании сообщит close-write (inotify)
+    Open,
+    /// Файл изменяется, но окончание записи не будет видно (опрос, изменение атрибутов)
+    Modified,
+    /// Запись закончена (close-write), файл переименован на место или удален
+    Complete,
+}
+
+/// Сводный набор изменений плагинов после успокоения файловой системы
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChangeSet {
+    pub added: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    /// Неизмененные плагины, которых касается изменение через сервисы, от которых они зависят
+    pub affected: Vec<PathBuf>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for ChangeSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "added {:?}, modified {:?}, removed {:?}", self.added, self.modified, self.removed)?;
+        if !self.affected.is_empty() {
+            write!(f, ", affected dependents {:?}", self.affected)?;
+        }
+        Ok(())
+    }
+}
+
+/// Запуск мониторинга директорий плагинов
+///
+/// Директории уже приведены к абсолютным путям и проверены при загрузке конфигурации.
+/// Плагин лежит либо прямо в директории (`plugins/foo_plugin.so`), либо в своей
+/// поддиректории вместе с манифестом и данными (`plugins/foo/foo_plugin.so`);
+/// любое изменение внутри поддиректории считается изменением ее библиотек.
+/// События файловой системы накапливаются, пока в течение тихого периода не перестанут
+/// приходить новые и пока запись файлов не закончится, после чего в канал отправляется
+/// один сводный [`ChangeSet`].
+///
+/// Переименование готового файла на место (`mv foo_plugin.so.tmp foo_plugin.so`) —
+/// основной способ установки: такое изменение обрабатывается сразу после тихого периода.
+///
+/// Изменения отсчитываются от набора `running`, с которым работает бот: плагин, у которого
+/// изменились только время изменения или права, а содержимое совпадает, не считается измененным
+/// (если не включен [`WatchSettings::restart_on_touch`]). Отличия директорий от `running`
+/// на момент запуска мониторинга (например, изменения, которые предыдущий мониторинг
+/// не успел отправить до reload) отправляются в канал первыми.
+pub fn start(
+    plugin_dirs: &[PathBuf],
+    settings: &WatchSettings,
+    naming: &LibraryNaming,
+    running: &Inventory,
+) -> Result<mpsc::Receiver<ChangeSet>> {
+    let (raw_tx, raw_rx) = mpsc::unbounded_channel::<(PathBuf, Write)>();
+    let pending_tx = raw_tx.clone();
+    let (backend, poll_interval) = (settings.backend, settings.poll_interval);
+
+    let mut native = match backend {
+        WatchBackend::Poll => None,
+        WatchBackend::Native | WatchBackend::Both => {
+            match notify::recommended_watcher(event_handler(raw_tx.clone(), plugin_dirs.to_vec(), settings, naming, true)) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    warn!("Failed to create native plugin watcher, falling back to polling: {}", e);
+                    None
+                }
+            }
+        }
+    };
+
+    // В режиме both опрашиваются все директории, в режиме native — только те, где события ненадежны
+    let mut poll_dirs = Vec::new();
+    for plugins_dir in plugin_dirs {
+        let mut poll = backend != WatchBackend::Native || native.is_none();
+
+        match unreliable_filesystem(plugins_dir) {
+            Some(filesystem) if backend == WatchBackend::Native => {
+                warn!("Plugins directory {:?} is on {}, which may not deliver change events, polling it", plugins_dir, filesystem);
+                poll = true;
+            }
+            _ => match native.as_mut().map(|watcher| watcher.watch(plugins_dir, RecursiveMode::Recursive)) {
+                Some(Ok(())) => info!("Started monitoring plugins directory: {:?}", plugins_dir),
+                Some(Err(e)) => {
+                    warn!("Failed to watch plugins directory {:?} natively, falling back to polling: {}", plugins_dir, e);
+                    poll = true;
+                }
+                None => {}
+            },
+        }
+
+        if poll {
+            poll_dirs.push(plugins_dir.clone());
+        }
+    }
+
+    let mut poller = None;
+    if !poll_dirs.is_empty() {
+        let config = notify::Config::default().with_poll_interval(poll_interval);
+        let mut watcher = PollWatcher::new(event_handler(raw_tx, plugin_dirs.to_vec(), settings, naming, false), config)
+            .map_err(|e| anyhow::anyhow!("Failed to create polling plugin watcher: {}", e))?;
+        for plugins_dir in &poll_dirs {
+            watcher
+                .watch(plugins_dir, RecursiveMode::Recursive)
+                .map_err(|e| anyhow::anyhow!("Failed to poll plugins directory {:?}: {}", plugins_dir, e))?;
+            info!("Started polling plugins directory every {:?}: {:?}", poll_interval, plugins_dir);
+        }
+        poller = Some(watcher);
+    }
+
+    if native.is_none() && poller.is_none() {
+        anyhow::bail!("No plugin watcher could be started");
+    }
+
+    // Отличия от работающего набора обрабатываются как события: известное состояние этих
+    // библиотек забывается, и после тихого периода они попадают в изменения
+    let mut known = scan(plugin_dirs, naming);
+    let pending = running.changes(&Inventory::scan(plugin_dirs, naming));
+    for path in &pending.added {
+        known.remove(path);
+    }
+    for path in pending.modified.iter().chain(&pending.removed) {
+        known.insert(path.clone(), None);
+    }
+    for path in pending.added.into_iter().chain(pending.modified).chain(pending.removed) {
+        let _ = pending_tx.send((path, Write::Modified));
+    }
+    let (changes_tx, changes_rx) = mpsc::channel(8);
+    let plugin_dirs = plugin_dirs.to_vec();
+    let quiet_period = settings.quiet_period;
+    let naming = naming.clone();
+    // Без сравнения содержимого любое изменение размера или времени изменения — изменение плагина
+    let baseline = if settings.restart_on_touch { None } else { Some(running.baseline()) };
+
+    tokio::spawn(async move {
+        // Watcher будет работать, пока жива эта задача, то есть пока жив получатель изменений
+        let _watchers = (native, poller);
+        let receiver_dropped = changes_tx.clone();
+        tokio::select! {
+            _ = debounce(raw_rx, changes_tx, plugin_dirs, naming, known, baseline, quiet_period) => {}
+            _ = receiver_dropped.closed() => {}
+        }
+    });
+
+    Ok(changes_rx)
+}
+
+/// Обработчик событий файловой системы, общий для всех способов мониторинга
+///
+/// `native` — события приходят от ОС; close-write бывает только у inotify.
+fn event_handler(
+    raw_tx: mpsc::UnboundedSender<(PathBuf, Write)>,
+    plugin_dirs: Vec<PathBuf>,
+    settings: &WatchSettings,
+    naming: &LibraryNaming,
+    native: bool,
+) -> impl Fn(notify::Result<NotifyEvent>) + Send + 'static {
+    let in_progress = if native && cfg!(target_os = "linux") { Write::Open } else { Write::Modified };
+    let (ignore, restart_on_touch) = (settings.ignore.clone(), settings.restart_on_touch);
+    let naming = naming.clone();
+
+    move |res| match res {
+        Ok(event) => {
+            if crate::signals::trace_enabled() {
+                info!("Plugin watcher event: {:?}", event);
+            }
+
+            let write = match event.kind {
+                EventKind::Create(CreateKind::Folder) => Write::Complete,
+                EventKind::Create(_) => in_progress,
+                EventKind::Modify(ModifyKind::Name(_)) => Write::Complete,
+                // События ОС о метаданных (touch, chmod) содержимое не меняют; опрос же сообщает
+                // так о любом изменении времени изменения, в том числе при записи
+                EventKind::Modify(ModifyKind::Metadata(_)) if native && !restart_on_touch => return,
+                EventKind::Modify(ModifyKind::Metadata(_)) => Write::Modified,
+                EventKind::Modify(_) => in_progress,
+                EventKind::Access(AccessKind::Close(AccessMode::Write)) => Write::Complete,
+                EventKind::Remove(_) => Write::Complete,
+                _ => return,
+            };
+
+            // Новые библиотеки другой платформы или исключенные списками не приводят к перезапуску
+            if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))) {
+                for path in event.paths.iter().filter(|path| path.exists()) {
+                    match naming.classify(path) {
+                        LibraryMatch::Foreign => warn!("Ignoring {:?}: not a loadable library on this platform", path),
+                        LibraryMatch::Denied => info!("Ignoring {:?}: excluded by plugin allow/deny lists", path),
+                        LibraryMatch::Library | LibraryMatch::Other => {}
+                    }
+                }
+            }
+
+            // Проверяем, что это событие связано с плагинами или сервисами и не попадает под игнорируемые шаблоны
+            let relevant =
+                |path: &PathBuf| locate(path, &plugin_dirs, &naming).is_some() && !is_ignored(path, &plugin_dirs, &ignore);
+            for path in event.paths.into_iter().filter(relevant) {
+                let _ = raw_tx.send((path, write));
+            }
+        }
+        Err(e) => {
+            warn!("Plugin watcher error: {:?}", e);
+        }
+    }
+}
+
+/// Файловая система директории, если inotify на ней может не видеть изменений
+///
+/// Сетевые и FUSE-файловые системы не сообщают об изменениях, сделанных на других
+/// машинах, а overlayfs — об изменениях в нижних слоях.
+#[cfg(target_os = "linux")]
+fn unreliable_filesystem(dir: &Path) -> Option<&'static str> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statfs заполняет переданную структуру и не сохраняет указатели
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    // Тип f_type зависит от архитектуры, сами значения — 32-битные
+    match stat.f_type as u32 {
+        0x6969 => Some("NFS"),
+        0x517b => Some("SMB"),
+        0xff53_4d42 => Some("CIFS"),
+        0xfe53_4d42 => Some("SMB2"),
+        0x0102_1997 => Some("9p"),
+        0x6573_5546 => Some("FUSE"),
+        0x794c_7630 => Some("overlayfs"),
+        0x00c3_6400 => Some("CephFS"),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unreliable_filesystem(_dir: &Path) -> Option<&'static str> {
+    None
+}
+
+/// Текущее состояние всех библиотек плагинов в директориях
+pub fn scan(plugin_dirs: &[PathBuf], naming: &LibraryNaming) -> HashMap<PathBuf, FileState> {
+    let mut known = HashMap::new();
+
+    for plugins_dir in plugin_dirs {
+        let entries = match std::fs::read_dir(plugins_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to scan plugins directory {:?}: {}", plugins_dir, e);
+                continue;
+            }
+        };
+
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if naming.is_library(&path) {
+                let state = file_state(&path);
+                known.insert(path, state);
+            } else if is_plugin_subdir_name(&path) && path.is_dir() {
+                for library in libraries_in(&path, naming) {
+                    let state = plugin_state(&library, plugin_dirs);
+                    known.insert(library, state);
+                }
+            }
+        }
+    }
+
+    known
+}
+
+/// Попадает ли путь (любая его часть внутри директории плагинов) под игнорируемые шаблоны
+fn is_ignored(path: &Path, plugin_dirs: &[PathBuf], ignore: &GlobSet) -> bool {
+    let relative = plugin_dirs.iter().find_map(|dir| path.strip_prefix(dir).ok()).unwrap_or(path);
+    relative.components().any(|component| ignore.is_match(component.as_os_str()))
+}
+
+/// Куда относится путь внутри директорий плагинов
+enum Location {
+    /// Библиотека прямо в директории плагинов
+    Library(PathBuf),
+    /// Поддиректория плагина `plugins/<name>/` или что-то внутри нее
+    PluginDir(PathBuf),
+}
+
+fn locate(path: &Path, plugin_dirs: &[PathBuf], naming: &LibraryNaming) -> Option<Location> {
+    let (plugins_dir, relative) = plugin_dirs
+        .iter()
+        .find_map(|dir| Some((dir, path.strip_prefix(dir).ok()?)))?;
+
+    let mut components = relative.components();
+    let first = components.next()?;
+    match components.next() {
+        None if naming.is_library(path) => Some(Location::Library(path.to_path_buf())),
+        // Сама поддиректория тоже: при переносе директории целиком событие приходит только о ней
+        _ => {
+            let plugin_dir = plugins_dir.join(first);
+            is_plugin_subdir_name(&plugin_dir).then_some(Location::PluginDir(plugin_dir))
+        }
+    }
+}
+
+/// Имя поддиректории плагина: не хранилище версий и не скрытая
+fn is_plugin_subdir_name(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name != STORE_DIR && !name.starts_with('.'),
+        None => false,
+    }
+}
+
+/// Библиотеки плагинов и сервисов в поддиректории плагина
+fn libraries_in(plugin_dir: &Path, naming: &LibraryNaming) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(plugin_dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| naming.is_library(path))
+        .collect()
+}
+
+/// Состояние плагина: файла библиотеки или всей его поддиректории
+fn plugin_state(library: &Path, plugin_dirs: &[PathBuf]) -> FileState {
+    let state = file_state(library)?;
+    match library.parent() {
+        Some(parent) if !plugin_dirs.iter().any(|dir| dir == parent) => tree_state(parent),
+        _ => Some(state),
+    }
+}
+
+fn file_state(path: &Path) -> FileState {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?;
+    Some((metadata.len(), modified, metadata_changed(&metadata).unwrap_or(modified)))
+}
+
+/// Время последнего изменения метаданных файла (ctime)
+#[cfg(unix)]
+fn metadata_changed(metadata: &std::fs::Metadata) -> Option<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+
+    let secs = u64::try_from(metadata.ctime()).ok()?;
+    let nanos = u32::try_from(metadata.ctime_nsec()).ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+#[cfg(not(unix))]
+fn metadata_changed(_metadata: &std::fs::Metadata) -> Option<SystemTime> {
+    None
+}
+
+/// Суммарный размер файлов и последнее время изменения внутри директории
+///
+/// Время изменения директорий учитывается, чтобы заметить удаление и переименование файлов.
+fn tree_state(dir: &Path) -> FileState {
+    let (mut len, mut modified, mut changed) = file_state(dir)?;
+    for entry in std::fs::read_dir(dir).ok()?.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let state = match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => tree_state(&path),
+            _ => file_state(&path),
+        };
+        if let Some((entry_len, entry_modified, entry_changed)) = state {
+            len += entry_len;
+            modified = modified.max(entry_modified);
+            changed = changed.max(entry_changed);
+        }
+    }
+    Some((len, modified, changed))
+}
+
+/// Библиотеки, к которым относятся изменившиеся пути
+fn attribute(
+    paths: BTreeSet<PathBuf>,
+    plugin_dirs: &[PathBuf],
+    naming: &LibraryNaming,
+    known: &HashMap<PathBuf, FileState>,
+) -> BTreeSet<PathBuf> {
+    let mut libraries = BTreeSet::new();
+    for path in paths {
+        match locate(&path, plugin_dirs, naming) {
+            Some(Location::Library(library)) => {
+                libraries.insert(library);
+            }
+            // Библиотеки, которые сейчас лежат в поддиректории, и которые лежали там раньше
+            Some(Location::PluginDir(plugin_dir)) => {
+                libraries.extend(libraries_in(&plugin_dir, naming));
+                libraries.extend(known.keys().filter(|library| library.parent() == Some(&plugin_dir)).cloned());
+            }
+            None => {}
+        }
+    }
+    libraries
+}
+
+/// Сбор событий в пачки и отправка сводных изменений
+async fn debounce(
+    mut raw_rx: mpsc::UnboundedReceiver<(PathBuf, Write)>,
+    changes_tx: mpsc::Sender<ChangeSet>,
+    plugin_dirs: Vec<PathBuf>,
+    naming: LibraryNaming,
+    mut known: HashMap<PathBuf, FileState>,
+    mut baseline: Option<HashMap<PathBuf, String>>,
+    quiet_period: Duration,
+) {
+    while let Some(first) = raw_rx.recv().await {
+        let mut pending = BTreeSet::new();
+
+        if !wait_until_stable(&mut raw_rx, first, &mut pending, quiet_period).await {
+            return;
+        }
+
+        let mut changes = ChangeSet::default();
+        for path in attribute(pending, &plugin_dirs, &naming, &known) {
+            let current = plugin_state(&path, &plugin_dirs);
+            let previous = known.get(&path).copied();
+
+            match (previous, current) {
+                (None, None) => {}
+                (None, Some(state)) => {
+                    known.insert(path.clone(), Some(state));
+                    if let Some(baseline) = baseline.as_mut() {
+                        remember(baseline, &path, content_hash(&path, &plugin_dirs));
+                    }
+                    changes.added.push(path);
+                }
+                (Some(_), None) => {
+                    known.remove(&path);
+                    if let Some(baseline) = baseline.as_mut() {
+                        baseline.remove(&path);
+                    }
+                    changes.removed.push(path);
+                }
+                (Some(old), Some(new)) if old != Some(new) => {
+                    known.insert(path.clone(), Some(new));
+
+                    // Размер, время изменения или метаданные другие, но содержимое то же (touch, chmod)
+                    if let Some(baseline) = baseline.as_mut() {
+                        let content = content_hash(&path, &plugin_dirs);
+                        if content.is_some() && content.as_ref() == baseline.get(&path) {
+                            info!("Plugin {:?} touched without content change, ignoring", path);
+                            continue;
+                        }
+                        remember(baseline, &path, content);
+                    }
+                    changes.modified.push(path);
+                }
+                (Some(_), Some(_)) => {}
+            }
+        }
+
+        if changes.is_empty() {
+            continue;
+        }
+
+        if changes_tx.send(changes).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Запомнить хеш содержимого плагина, `None` — хеш не удалось посчитать
+fn remember(baseline: &mut HashMap<PathBuf, String>, path: &Path, content: Option<String>) {
+    match content {
+        Some(content) => baseline.insert(path.to_path_buf(), content),
+        None => baseline.remove(path),
+    };
+}
+
+/// Ожидание, пока события не прекратятся и запись файлов не закончится
+///
+/// Если о каждом файле пришло событие окончания записи (close-write, переименование
+/// на место, удаление), достаточно одного тихого периода. Файлы, открытые на запись,
+/// ждут close-write (но не дольше [`STALLED_WRITE_PERIODS`] тихих периодов без изменений).
+/// Для остальных файлов (например, при опросе, где close-write нет) нужно, чтобы два
+/// тихих периода подряд их размер и время изменения совпадали.
+/// Возвращает `false`, если источник событий закрылся.
+async fn wait_until_stable(
+    raw_rx: &mut mpsc::UnboundedReceiver<(PathBuf, Write)>,
+    first: (PathBuf, Write),
+    pending: &mut BTreeSet<PathBuf>,
+    quiet_period: Duration,
+) -> bool {
+    let mut in_progress: BTreeMap<PathBuf, Write> = BTreeMap::new();
+    let mut previous: Option<Vec<FileState>> = None;
+    let mut unchanged_periods = 0;
+    let mut event = Some(first);
+
+    loop {
+        if let Some((path, write)) = event.take() {
+            match write {
+                // Файл, открытый на запись, остается открытым, даже если потом менялись его атрибуты
+                Write::Modified if in_progress.get(&path) == Some(&Write::Open) => {}
+                Write::Open | Write::Modified => {
+                    in_progress.insert(path.clone(), write);
+                }
+                Write::Complete => {
+                    in_progress.remove(&path);
+                }
+            }
+            pending.insert(path);
+            previous = None;
+            unchanged_periods = 0;
+        }
+
+        match tokio::time::timeout(quiet_period, raw_rx.recv()).await {
+            Ok(Some(next)) => event = Some(next),
+            Ok(None) => return false,
+            Err(_) if in_progress.is_empty() => return true,
+            Err(_) => {
+                let current: Vec<FileState> = in_progress.keys().map(|path| file_state(path)).collect();
+                if previous.as_ref() == Some(&current) {
+                    unchanged_periods += 1;
+                } else {
+                    unchanged_periods = 0;
+                }
+                previous = Some(current);
+
+                let open = in_progress.values().any(|write| *write == Write::Open);
+                let required = if open { STALLED_WRITE_PERIODS } else { 1 };
+                if unchanged_periods >= required {
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{library, TempDir};
+
+    const QUIET: Duration = Duration::from_millis(50);
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn finished_writes_are_collected_after_one_quiet_period() {
+        block_on(async {
+            let (raw_tx, mut raw_rx) = mpsc::unbounded_channel();
+            raw_tx.send((PathBuf::from("/plugins/bar_plugin.so"), Write::Complete)).unwrap();
+
+            let mut pending = BTreeSet::new();
+            let first = (PathBuf::from("/plugins/foo_plugin.so"), Write::Complete);
+            assert!(wait_until_stable(&mut raw_rx, first, &mut pending, QUIET).await);
+            assert_eq!(
+                pending,
+                BTreeSet::from([PathBuf::from("/plugins/bar_plugin.so"), PathBuf::from("/plugins/foo_plugin.so")])
+            );
+
+            drop(raw_tx);
+            let first = (PathBuf::from("/plugins/foo_plugin.so"), Write::Complete);
+            assert!(!wait_until_stable(&mut raw_rx, first, &mut BTreeSet::new(), QUIET).await);
+        });
+    }
+
+    #[test]
+    fn open_files_wait_for_close_write() {
+        let dir = TempDir::new("watcher-open");
+        let path = dir.write(&library("foo_plugin"), "partial");
+
+        block_on(async {
+            let (raw_tx, mut raw_rx) = mpsc::unbounded_channel();
+            let started = tokio::time::Instant::now();
+            let close_write = async {
+                tokio::time::sleep(QUIET * 4).await;
+                raw_tx.send((path.clone(), Write::Complete)).unwrap();
+            };
+
+            let mut pending = BTreeSet::new();
+            let (stable, _) =
+                tokio::join!(wait_until_stable(&mut raw_rx, (path.clone(), Write::Open), &mut pending, QUIET), close_write);
+            assert!(stable);
+            // Дождались close-write, а не истечения STALLED_WRITE_PERIODS тихих периодов
+            assert!(started.elapsed() >= QUIET * 5);
+            assert!(started.elapsed() < QUIET * STALLED_WRITE_PERIODS);
+        });
+    }
+
+    #[test]
+    fn events_are_attributed_to_plugin_libraries() {
+        let dir = TempDir::new("watcher-attribute");
+        let plugin_dirs = vec![dir.path().to_path_buf()];
+        let naming = LibraryNaming::default();
+        let top_level = dir.write(&library("foo_plugin"), "foo");
+        let in_subdir = dir.write(&format!("bar/{}", library("bar_plugin")), "bar");
+        let data = dir.write("bar/data/config.json", "{}");
+        let stored = dir.write(&format!("releases/baz/1/{}", library("baz_plugin")), "baz");
+        // Библиотека, которая была в поддиректории до изменения
+        let known = HashMap::from([(dir.path().join(format!("bar/{}", library("old_plugin"))), None)]);
+
+        let paths = BTreeSet::from([top_level.clone(), data, stored, dir.path().join("notes.txt")]);
+        let libraries = attribute(paths, &plugin_dirs, &naming, &known);
+        let old = dir.path().join(format!("bar/{}", library("old_plugin")));
+        assert_eq!(libraries, BTreeSet::from([top_level, in_subdir, old]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn touch_and_chmod_without_content_change_are_ignored() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("watcher-touch");
+        let plugin_dirs = vec![dir.path().to_path_buf()];
+        let naming = LibraryNaming::default();
+        let path = dir.write(&library("foo_plugin"), "foo");
+        let known = scan(&plugin_dirs, &naming);
+        let baseline = known.keys().filter_map(|path| Some((path.clone(), content_hash(path, &plugin_dirs)?))).collect();
+
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        let (changes_tx, mut changes_rx) = mpsc::channel(8);
+        let events = async {
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)).unwrap();
+            raw_tx.send((path.clone(), Write::Modified)).unwrap();
+            tokio::time::sleep(QUIET * 4).await;
+
+            std::fs::write(&path, "foo, updated").unwrap();
+            raw_tx.send((path.clone(), Write::Complete)).unwrap();
+            tokio::time::sleep(QUIET * 4).await;
+            drop(raw_tx);
+        };
+
+        block_on(async {
+            tokio::join!(events, debounce(raw_rx, changes_tx, plugin_dirs.clone(), naming, known, Some(baseline), QUIET));
+        });
+
+        // Только изменение содержимого, без отдельного изменения для touch и chmod
+        let changes = changes_rx.try_recv().unwrap();
+        assert_eq!(changes.modified, vec![path]);
+        assert!(changes_rx.try_recv().is_err());
+    }
+}